@@ -15,15 +15,17 @@ use serde::{Deserialize, Serialize};
 )]
 #[sea_orm(table_name = "Account")]
 pub struct Model {
-    #[sea_orm(primary_key, auto_increment = false, column_type = "Text")]
+    #[sea_orm(primary_key, auto_increment = false)]
+    #[serde(deserialize_with = "crate::id::deserialize")]
     pub id: String,
-    #[sea_orm(column_name = "userId", column_type = "Text")]
+    #[sea_orm(column_name = "userId")]
+    #[serde(deserialize_with = "crate::id::deserialize")]
     pub user_id: String,
     #[sea_orm(column_type = "Text")]
     pub r#type: String,
-    #[sea_orm(column_type = "Text")]
     pub provider: String,
-    #[sea_orm(column_name = "providerAccountId", column_type = "Text")]
+    #[sea_orm(column_name = "providerAccountId")]
+    #[serde(deserialize_with = "crate::id::deserialize")]
     pub provider_account_id: String,
     #[sea_orm(column_type = "Text", nullable)]
     pub refresh_token: Option<String>,
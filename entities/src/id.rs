@@ -0,0 +1,69 @@
+//! Lenient deserialization for id fields.
+//!
+//! Some clients send ids as JSON numbers, so integers are accepted and stored
+//! as their decimal string. Ids must be non-empty, at most [`MAX_ID_LEN`]
+//! bytes and free of whitespace and control characters.
+
+use std::fmt;
+
+use serde::{de, Deserialize, Deserializer};
+
+pub const MAX_ID_LEN: usize = 255;
+
+pub fn deserialize<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_any(IdVisitor)
+}
+
+/// [`deserialize`] for optional fields, used with `#[serde(default)]`.
+pub fn deserialize_option<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct Id(String);
+
+    impl<'de> Deserialize<'de> for Id {
+        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            deserialize(deserializer).map(Id)
+        }
+    }
+
+    Option::<Id>::deserialize(deserializer).map(|value| value.map(|Id(id)| id))
+}
+
+struct IdVisitor;
+
+impl<'de> de::Visitor<'de> for IdVisitor {
+    type Value = String;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "a string or integer id of at most {MAX_ID_LEN} bytes without whitespace"
+        )
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+        Ok(v.to_string())
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+        Ok(v.to_string())
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        let valid = !v.is_empty()
+            && v.len() <= MAX_ID_LEN
+            && !v.chars().any(|c| c.is_whitespace() || c.is_control());
+        if valid {
+            Ok(v.to_owned())
+        } else {
+            Err(E::invalid_value(de::Unexpected::Str(v), &self))
+        }
+    }
+}
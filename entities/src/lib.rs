@@ -3,6 +3,8 @@
 pub mod prelude;
 
 pub mod account;
+pub mod id;
 pub mod session;
+pub mod timestamp;
 pub mod user;
 pub mod verification_token;
@@ -15,13 +15,13 @@ use serde::{Deserialize, Serialize};
 )]
 #[sea_orm(table_name = "Session")]
 pub struct Model {
-    #[sea_orm(primary_key, auto_increment = false, column_type = "Text")]
+    #[sea_orm(primary_key, auto_increment = false)]
     pub id: String,
-    #[sea_orm(column_name = "sessionToken", column_type = "Text")]
+    #[sea_orm(column_name = "sessionToken")]
     pub session_token: String,
-    #[sea_orm(column_name = "userId", column_type = "Text")]
+    #[sea_orm(column_name = "userId")]
     pub user_id: String,
-    pub expires: DateTimeWithTimeZone,
+    pub expires: DateTimeUtc,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -0,0 +1,77 @@
+//! Lenient deserialization for `expires` fields.
+//!
+//! NextAuth adapters disagree on how they send expiry times, so an RFC 3339
+//! string, Unix epoch seconds and Unix epoch milliseconds are all accepted.
+//! Numbers may also arrive as strings, as they do in form bodies.
+
+use std::fmt;
+
+use chrono::{DateTime, TimeZone, Utc};
+use serde::{de, Deserialize, Deserializer};
+
+/// Epoch values this large are read as milliseconds. As seconds they would
+/// fall after the year 5000.
+const MILLIS_THRESHOLD: u64 = 100_000_000_000;
+
+pub fn deserialize<'de, D>(deserializer: D) -> Result<DateTime<Utc>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_any(TimestampVisitor)
+}
+
+/// [`deserialize`] for optional fields, used with `#[serde(default)]`.
+pub fn deserialize_option<'de, D>(deserializer: D) -> Result<Option<DateTime<Utc>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct Timestamp(DateTime<Utc>);
+
+    impl<'de> Deserialize<'de> for Timestamp {
+        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            deserialize(deserializer).map(Timestamp)
+        }
+    }
+
+    Option::<Timestamp>::deserialize(deserializer).map(|value| value.map(|Timestamp(t)| t))
+}
+
+struct TimestampVisitor;
+
+impl<'de> de::Visitor<'de> for TimestampVisitor {
+    type Value = DateTime<Utc>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an RFC 3339 timestamp or Unix epoch seconds or milliseconds")
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+        from_epoch(v).ok_or_else(|| E::invalid_value(de::Unexpected::Signed(v), &self))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+        i64::try_from(v)
+            .ok()
+            .and_then(from_epoch)
+            .ok_or_else(|| E::invalid_value(de::Unexpected::Unsigned(v), &self))
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        DateTime::parse_from_rfc3339(v)
+            .map(|t| t.with_timezone(&Utc))
+            .ok()
+            .or_else(|| v.trim().parse().ok().and_then(from_epoch))
+            .ok_or_else(|| E::invalid_value(de::Unexpected::Str(v), &self))
+    }
+}
+
+fn from_epoch(value: i64) -> Option<DateTime<Utc>> {
+    if value.unsigned_abs() >= MILLIS_THRESHOLD {
+        Utc.timestamp_millis_opt(value).single()
+    } else {
+        Utc.timestamp_opt(value, 0).single()
+    }
+}
@@ -1,6 +1,7 @@
 //! `SeaORM` Entity. Generated by sea-orm-codegen 0.12.1
 
-use sea_orm::entity::prelude::*;
+use chrono::Utc;
+use sea_orm::{entity::prelude::*, Set};
 use serde::{Deserialize, Serialize};
 
 #[derive(
@@ -15,17 +16,32 @@ use serde::{Deserialize, Serialize};
 )]
 #[sea_orm(table_name = "User")]
 pub struct Model {
-    #[sea_orm(primary_key, auto_increment = false, column_type = "Text")]
+    #[sea_orm(primary_key, auto_increment = false)]
     #[serde(default)] // for skipping id in put request
     pub id: String,
     #[sea_orm(column_type = "Text", nullable)]
     pub name: Option<String>,
-    #[sea_orm(column_type = "Text", nullable)]
+    #[sea_orm(nullable)]
     pub email: Option<String>,
     #[sea_orm(column_name = "emailVerified")]
     pub email_verified: Option<DateTimeWithTimeZone>,
     #[sea_orm(column_type = "Text", nullable)]
     pub image: Option<String>,
+    /// BCP 47 language tag, e.g. `en-GB`.
+    #[sea_orm(nullable)]
+    pub locale: Option<String>,
+    /// IANA time zone name, e.g. `Europe/London`.
+    #[sea_orm(nullable)]
+    pub timezone: Option<String>,
+    /// Display handle shown instead of the user's name.
+    #[sea_orm(nullable)]
+    pub handle: Option<String>,
+    #[sea_orm(column_name = "createdAt")]
+    #[serde(default)] // set by the server
+    pub created_at: DateTimeWithTimeZone,
+    #[sea_orm(column_name = "updatedAt")]
+    #[serde(default)] // set by the server
+    pub updated_at: DateTimeWithTimeZone,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -48,4 +64,17 @@ impl Related<super::session::Entity> for Entity {
     }
 }
 
-impl ActiveModelBehavior for ActiveModel {}
+#[async_trait::async_trait]
+impl ActiveModelBehavior for ActiveModel {
+    async fn before_save<C>(mut self, _db: &C, insert: bool) -> Result<Self, DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        let now: DateTimeWithTimeZone = Utc::now().into();
+        if insert {
+            self.created_at = Set(now);
+        }
+        self.updated_at = Set(now);
+        Ok(self)
+    }
+}
@@ -1,6 +1,7 @@
 //! `SeaORM` Entity. Generated by sea-orm-codegen 0.12.1
 
-use sea_orm::entity::prelude::*;
+use chrono::Utc;
+use sea_orm::{entity::prelude::*, Set};
 use serde::{Deserialize, Serialize};
 
 #[derive(Default, Serialize, Deserialize, Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
@@ -8,14 +9,27 @@ use serde::{Deserialize, Serialize};
 pub struct Model {
     #[sea_orm(primary_key)]
     pub id: i64,
-    #[sea_orm(column_type = "Text")]
     pub identifier: String,
-    #[sea_orm(column_type = "Text")]
     pub token: String,
-    pub expires: DateTimeWithTimeZone,
+    #[serde(deserialize_with = "crate::timestamp::deserialize")]
+    pub expires: DateTimeUtc,
+    #[sea_orm(column_name = "createdAt")]
+    #[serde(default)] // set by the server
+    pub created_at: DateTimeWithTimeZone,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {}
 
-impl ActiveModelBehavior for ActiveModel {}
+#[async_trait::async_trait]
+impl ActiveModelBehavior for ActiveModel {
+    async fn before_save<C>(mut self, _db: &C, insert: bool) -> Result<Self, DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        if insert {
+            self.created_at = Set(Utc::now().into());
+        }
+        Ok(self)
+    }
+}
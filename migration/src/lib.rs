@@ -1,12 +1,30 @@
 pub use sea_orm_migration::prelude::*;
 
 mod m20220101_000001_create_table;
+mod m20261016_000001_unique_user_email;
+mod m20261016_000002_user_timestamps;
+mod m20261016_000003_unique_verification_token;
+mod m20261016_000004_session_indexes;
+mod m20261016_000005_account_provider_index;
+mod m20261016_000006_user_profile_fields;
+mod m20261016_000007_verification_token_created_at;
+mod m20261016_000008_varchar_key_columns;
 
 pub struct Migrator;
 
 #[async_trait::async_trait]
 impl MigratorTrait for Migrator {
     fn migrations() -> Vec<Box<dyn MigrationTrait>> {
-        vec![Box::new(m20220101_000001_create_table::Migration)]
+        vec![
+            Box::new(m20220101_000001_create_table::Migration),
+            Box::new(m20261016_000001_unique_user_email::Migration),
+            Box::new(m20261016_000002_user_timestamps::Migration),
+            Box::new(m20261016_000003_unique_verification_token::Migration),
+            Box::new(m20261016_000004_session_indexes::Migration),
+            Box::new(m20261016_000005_account_provider_index::Migration),
+            Box::new(m20261016_000006_user_profile_fields::Migration),
+            Box::new(m20261016_000007_verification_token_created_at::Migration),
+            Box::new(m20261016_000008_varchar_key_columns::Migration),
+        ]
     }
 }
@@ -1,54 +1,126 @@
-use entities::{account, session, user, verification_token};
-use sea_orm_migration::{
-    prelude::*,
-    sea_orm::{DbBackend, EntityTrait, Schema},
-};
+use sea_orm_migration::{prelude::*, sea_orm::DbBackend};
 
+/// Creates the original four tables. The columns are spelled out rather than
+/// derived from the entities, so later entity changes can't alter what this
+/// migration creates; columns added and types changed since come from their
+/// own migrations.
 #[derive(DeriveMigrationName)]
 pub struct Migration;
 
 #[async_trait::async_trait]
 impl MigrationTrait for Migration {
     async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
-        let stmts = vec![
-            get_seaorm_create_stmt(verification_token::Entity),
-            get_seaorm_create_stmt(user::Entity),
-            get_seaorm_create_stmt(account::Entity),
-            get_seaorm_create_stmt(session::Entity),
-        ];
-
-        for stmt in stmts {
-            manager.create_table(stmt.to_owned()).await?;
+        let backend = manager.get_database_backend();
+
+        // SQLite only auto-increments an `INTEGER PRIMARY KEY`, and its
+        // integers are 64-bit anyway
+        let mut token_id = ColumnDef::new(Alias::new("id"));
+        match backend {
+            DbBackend::Sqlite => token_id.integer(),
+            DbBackend::Postgres | DbBackend::MySql => token_id.big_integer(),
+        };
+        token_id.not_null().auto_increment().primary_key();
+        let verification_token = Table::create()
+            .table(Alias::new("VerificationToken"))
+            .if_not_exists()
+            .col(&mut token_id)
+            .col(key_text(backend, "identifier").not_null())
+            .col(key_text(backend, "token").not_null())
+            .col(
+                ColumnDef::new(Alias::new("expires"))
+                    .timestamp_with_time_zone()
+                    .not_null(),
+            )
+            .to_owned();
+
+        let user = Table::create()
+            .table(Alias::new("User"))
+            .if_not_exists()
+            .col(&mut text_id(backend))
+            .col(ColumnDef::new(Alias::new("name")).text())
+            .col(&mut key_text(backend, "email"))
+            .col(ColumnDef::new(Alias::new("emailVerified")).timestamp_with_time_zone())
+            .col(ColumnDef::new(Alias::new("image")).text())
+            .to_owned();
+
+        let account = Table::create()
+            .table(Alias::new("Account"))
+            .if_not_exists()
+            .col(&mut text_id(backend))
+            .col(key_text(backend, "userId").not_null())
+            .col(ColumnDef::new(Alias::new("type")).text().not_null())
+            .col(key_text(backend, "provider").not_null())
+            .col(key_text(backend, "providerAccountId").not_null())
+            .col(ColumnDef::new(Alias::new("refresh_token")).text())
+            .col(ColumnDef::new(Alias::new("access_token")).text())
+            .col(ColumnDef::new(Alias::new("expires_at")).integer())
+            .col(ColumnDef::new(Alias::new("token_type")).text())
+            .col(ColumnDef::new(Alias::new("scope")).text())
+            .col(ColumnDef::new(Alias::new("id_token")).text())
+            .col(ColumnDef::new(Alias::new("session_state")).text())
+            .foreign_key(&mut user_fk("Account"))
+            .to_owned();
+
+        let session = Table::create()
+            .table(Alias::new("Session"))
+            .if_not_exists()
+            .col(&mut text_id(backend))
+            .col(key_text(backend, "sessionToken").not_null())
+            .col(key_text(backend, "userId").not_null())
+            .col(
+                ColumnDef::new(Alias::new("expires"))
+                    .timestamp_with_time_zone()
+                    .not_null(),
+            )
+            .foreign_key(&mut user_fk("Session"))
+            .to_owned();
+
+        for stmt in [verification_token, user, account, session] {
+            manager.create_table(stmt).await?;
         }
 
         Ok(())
     }
 
     async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
-        let stmts = vec![
-            get_seaorm_drop_stmt(verification_token::Entity),
-            get_seaorm_drop_stmt(session::Entity),
-            get_seaorm_drop_stmt(account::Entity),
-            get_seaorm_drop_stmt(user::Entity),
-        ];
-
-        for stmt in stmts {
-            manager.drop_table(stmt.to_owned()).await?;
+        for table in ["VerificationToken", "Session", "Account", "User"] {
+            manager
+                .drop_table(
+                    Table::drop()
+                        .table(Alias::new(table))
+                        .if_exists()
+                        .to_owned(),
+                )
+                .await?;
         }
 
         Ok(())
     }
 }
 
-fn get_seaorm_create_stmt<E: EntityTrait>(e: E) -> TableCreateStatement {
-    let schema = Schema::new(DbBackend::Postgres);
+/// The client-assigned text primary key shared by `User`, `Account` and `Session`.
+fn text_id(backend: DbBackend) -> ColumnDef {
+    key_text(backend, "id").not_null().primary_key().to_owned()
+}
 
-    schema
-        .create_table_from_entity(e)
-        .if_not_exists()
-        .to_owned()
+/// A `TEXT` column that is, or later gets, a key or an index. MySQL can't key
+/// a `TEXT` column without a prefix length, so there it's a `VARCHAR(255)`.
+fn key_text(backend: DbBackend, name: &str) -> ColumnDef {
+    let mut column = ColumnDef::new(Alias::new(name));
+    match backend {
+        DbBackend::MySql => column.string(),
+        DbBackend::Postgres | DbBackend::Sqlite => column.text(),
+    };
+    column
 }
 
-fn get_seaorm_drop_stmt<E: EntityTrait>(e: E) -> TableDropStatement {
-    Table::drop().table(e).if_exists().to_owned()
+/// `table.userId` referencing `User.id`, so a user's rows go with them.
+fn user_fk(table: &str) -> ForeignKeyCreateStatement {
+    ForeignKey::create()
+        .name(format!("fk-{table}-userId"))
+        .from(Alias::new(table), Alias::new("userId"))
+        .to(Alias::new("User"), Alias::new("id"))
+        .on_delete(ForeignKeyAction::Cascade)
+        .on_update(ForeignKeyAction::Cascade)
+        .to_owned()
 }
@@ -0,0 +1,82 @@
+use sea_orm_migration::{prelude::*, sea_orm::DbBackend};
+
+const INDEX_NAME: &str = "idx-user-email-lower";
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        // the index would fail on these after the emails had already been
+        // rewritten, so stop before changing anything
+        let duplicates = case_insensitive_duplicates(manager).await?;
+        if !duplicates.is_empty() {
+            return Err(DbErr::Migration(format!(
+                "more than one user has each of these emails when ignoring case: {}. \
+                 Merge those users (see migration/README.md) and run the migration again",
+                duplicates.join(", ")
+            )));
+        }
+
+        // existing rows were written before emails were normalised
+        let normalise = Query::update()
+            .table(Alias::new("User"))
+            .value(
+                Alias::new("email"),
+                Func::lower(Expr::col(Alias::new("email"))),
+            )
+            .to_owned();
+        manager.exec_stmt(normalise).await?;
+
+        // sea-query can't express functional indexes, so each backend gets raw SQL
+        let create = match manager.get_database_backend() {
+            DbBackend::MySql => {
+                format!("CREATE UNIQUE INDEX `{INDEX_NAME}` ON `User` ((lower(email)))")
+            }
+            DbBackend::Postgres | DbBackend::Sqlite => {
+                format!(
+                    r#"CREATE UNIQUE INDEX IF NOT EXISTS "{INDEX_NAME}" ON "User" (lower(email))"#
+                )
+            }
+        };
+        db.execute_unprepared(&create).await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let drop = match manager.get_database_backend() {
+            DbBackend::MySql => format!("DROP INDEX `{INDEX_NAME}` ON `User`"),
+            DbBackend::Postgres | DbBackend::Sqlite => {
+                format!(r#"DROP INDEX IF EXISTS "{INDEX_NAME}""#)
+            }
+        };
+        manager.get_connection().execute_unprepared(&drop).await?;
+
+        Ok(())
+    }
+}
+
+/// The lowercased emails that more than one user shares.
+async fn case_insensitive_duplicates(manager: &SchemaManager<'_>) -> Result<Vec<String>, DbErr> {
+    let lower_email = SimpleExpr::from(Func::lower(Expr::col(Alias::new("email"))));
+    let query = Query::select()
+        .expr_as(lower_email.clone(), Alias::new("email"))
+        .from(Alias::new("User"))
+        .and_where(Expr::col(Alias::new("email")).is_not_null())
+        .add_group_by([lower_email.clone()])
+        .and_having(Expr::expr(Func::count(Expr::col(Alias::new("id")))).gt(1))
+        .order_by_expr(lower_email, Order::Asc)
+        .to_owned();
+    let backend = manager.get_database_backend();
+    manager
+        .get_connection()
+        .query_all(backend.build(&query))
+        .await?
+        .iter()
+        .map(|row| row.try_get("", "email"))
+        .collect()
+}
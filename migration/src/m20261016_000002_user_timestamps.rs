@@ -0,0 +1,93 @@
+use entities::user;
+use sea_orm_migration::{
+    prelude::*,
+    sea_orm::{prelude::DateTimeWithTimeZone, ConnectionTrait, DbBackend},
+};
+
+/// Fills the new columns on SQLite until the existing rows are backfilled,
+/// since it can't add a NOT NULL column without a constant default. SQLite
+/// has no `ALTER COLUMN` to drop it again, so it stays; the server always sets
+/// these columns itself.
+pub(crate) const SQLITE_PLACEHOLDER: &str = "1970-01-01T00:00:00+00:00";
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let backend = manager.get_database_backend();
+        let now: DateTimeWithTimeZone = chrono::Utc::now().into();
+
+        // databases created while the first migration built its tables from
+        // the entities already have the columns
+        let columns = [
+            (user::Column::CreatedAt, "createdAt"),
+            (user::Column::UpdatedAt, "updatedAt"),
+        ];
+        for (column, name) in columns {
+            if manager.has_column("User", name).await? {
+                continue;
+            }
+
+            let mut def = ColumnDef::new(column);
+            def.timestamp_with_time_zone().not_null();
+            match backend {
+                DbBackend::Sqlite => def.default(SQLITE_PLACEHOLDER),
+                DbBackend::Postgres | DbBackend::MySql => def.default(Expr::current_timestamp()),
+            };
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(user::Entity)
+                        .add_column(&mut def)
+                        .to_owned(),
+                )
+                .await?;
+
+            // existing users get the time of the upgrade, and the default is
+            // dropped again where the database allows it
+            match backend {
+                DbBackend::Sqlite => {
+                    let backfill = Query::update()
+                        .table(user::Entity)
+                        .value(column, now)
+                        .to_owned();
+                    manager.exec_stmt(backfill).await?;
+                }
+                DbBackend::Postgres => {
+                    manager
+                        .get_connection()
+                        .execute_unprepared(&format!(
+                            r#"ALTER TABLE "User" ALTER COLUMN "{name}" DROP DEFAULT"#
+                        ))
+                        .await?;
+                }
+                DbBackend::MySql => {
+                    manager
+                        .get_connection()
+                        .execute_unprepared(&format!(
+                            "ALTER TABLE `User` ALTER COLUMN `{name}` DROP DEFAULT"
+                        ))
+                        .await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        for column in [user::Column::CreatedAt, user::Column::UpdatedAt] {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(user::Entity)
+                        .drop_column(column)
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+}
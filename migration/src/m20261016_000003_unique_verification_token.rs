@@ -0,0 +1,35 @@
+use entities::verification_token;
+use sea_orm_migration::prelude::*;
+
+const INDEX_NAME: &str = "idx-verification-token-identifier-token";
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_index(
+                Index::create()
+                    .name(INDEX_NAME)
+                    .table(verification_token::Entity)
+                    .col(verification_token::Column::Identifier)
+                    .col(verification_token::Column::Token)
+                    .unique()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name(INDEX_NAME)
+                    .table(verification_token::Entity)
+                    .to_owned(),
+            )
+            .await
+    }
+}
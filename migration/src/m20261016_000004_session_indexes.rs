@@ -0,0 +1,42 @@
+use entities::session;
+use sea_orm_migration::prelude::*;
+
+const TOKEN_INDEX: &str = "idx-session-session-token";
+const USER_INDEX: &str = "idx-session-user-id";
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_index(
+                Index::create()
+                    .name(TOKEN_INDEX)
+                    .table(session::Entity)
+                    .col(session::Column::SessionToken)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name(USER_INDEX)
+                    .table(session::Entity)
+                    .col(session::Column::UserId)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        for name in [USER_INDEX, TOKEN_INDEX] {
+            manager
+                .drop_index(Index::drop().name(name).table(session::Entity).to_owned())
+                .await?;
+        }
+        Ok(())
+    }
+}
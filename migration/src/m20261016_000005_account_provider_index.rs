@@ -0,0 +1,35 @@
+use entities::account;
+use sea_orm_migration::prelude::*;
+
+const INDEX_NAME: &str = "idx-account-provider-provider-account-id";
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_index(
+                Index::create()
+                    .name(INDEX_NAME)
+                    .table(account::Entity)
+                    .col(account::Column::Provider)
+                    .col(account::Column::ProviderAccountId)
+                    .unique()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name(INDEX_NAME)
+                    .table(account::Entity)
+                    .to_owned(),
+            )
+            .await
+    }
+}
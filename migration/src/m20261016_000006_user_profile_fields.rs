@@ -0,0 +1,53 @@
+use entities::user;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // databases created while the first migration built its tables from
+        // the entities already have the columns
+        let columns = [
+            (user::Column::Locale, "locale"),
+            (user::Column::Timezone, "timezone"),
+            (user::Column::Handle, "handle"),
+        ];
+        for (column, name) in columns {
+            if manager.has_column("User", name).await? {
+                continue;
+            }
+
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(user::Entity)
+                        .add_column(ColumnDef::new(column).string().null())
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        for column in [
+            user::Column::Locale,
+            user::Column::Timezone,
+            user::Column::Handle,
+        ] {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(user::Entity)
+                        .drop_column(column)
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+}
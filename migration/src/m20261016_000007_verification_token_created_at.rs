@@ -0,0 +1,79 @@
+use entities::verification_token;
+use sea_orm_migration::{
+    prelude::*,
+    sea_orm::{prelude::DateTimeWithTimeZone, ConnectionTrait, DbBackend},
+};
+
+use crate::m20261016_000002_user_timestamps::SQLITE_PLACEHOLDER;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // databases created while the first migration built its tables from
+        // the entities already have the column
+        if manager.has_column("VerificationToken", "createdAt").await? {
+            return Ok(());
+        }
+        let backend = manager.get_database_backend();
+        let now: DateTimeWithTimeZone = chrono::Utc::now().into();
+
+        let mut def = ColumnDef::new(verification_token::Column::CreatedAt);
+        def.timestamp_with_time_zone().not_null();
+        match backend {
+            DbBackend::Sqlite => def.default(SQLITE_PLACEHOLDER),
+            DbBackend::Postgres | DbBackend::MySql => def.default(Expr::current_timestamp()),
+        };
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(verification_token::Entity)
+                    .add_column(&mut def)
+                    .to_owned(),
+            )
+            .await?;
+
+        // existing tokens get the time of the upgrade, and the default is
+        // dropped again where the database allows it
+        match backend {
+            DbBackend::Sqlite => {
+                let backfill = Query::update()
+                    .table(verification_token::Entity)
+                    .value(verification_token::Column::CreatedAt, now)
+                    .to_owned();
+                manager.exec_stmt(backfill).await?;
+            }
+            DbBackend::Postgres => {
+                manager
+                    .get_connection()
+                    .execute_unprepared(
+                        r#"ALTER TABLE "VerificationToken" ALTER COLUMN "createdAt" DROP DEFAULT"#,
+                    )
+                    .await?;
+            }
+            DbBackend::MySql => {
+                manager
+                    .get_connection()
+                    .execute_unprepared(
+                        "ALTER TABLE `VerificationToken` ALTER COLUMN `createdAt` DROP DEFAULT",
+                    )
+                    .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(verification_token::Entity)
+                    .drop_column(verification_token::Column::CreatedAt)
+                    .to_owned(),
+            )
+            .await
+    }
+}
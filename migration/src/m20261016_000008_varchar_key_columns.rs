@@ -0,0 +1,72 @@
+use sea_orm_migration::{prelude::*, sea_orm::DbBackend};
+
+/// The `TEXT` columns the entities now declare as `String`, with whether they
+/// may be null. The first migration already makes them `VARCHAR` on MySQL, and
+/// SQLite stores both as text, so only Postgres changes.
+const COLUMNS: [(&str, &str, bool); 11] = [
+    ("User", "id", false),
+    ("User", "email", true),
+    ("Account", "id", false),
+    ("Account", "userId", false),
+    ("Account", "provider", false),
+    ("Account", "providerAccountId", false),
+    ("Session", "id", false),
+    ("Session", "sessionToken", false),
+    ("Session", "userId", false),
+    ("VerificationToken", "identifier", false),
+    ("VerificationToken", "token", false),
+];
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        if manager.get_database_backend() != DbBackend::Postgres {
+            return Ok(());
+        }
+
+        for (table, name, nullable) in COLUMNS {
+            let mut column = ColumnDef::new(Alias::new(name));
+            column.string();
+            if !nullable {
+                column.not_null();
+            }
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(Alias::new(table))
+                        .modify_column(&mut column)
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        if manager.get_database_backend() != DbBackend::Postgres {
+            return Ok(());
+        }
+
+        for (table, name, nullable) in COLUMNS {
+            let mut column = ColumnDef::new(Alias::new(name));
+            column.text();
+            if !nullable {
+                column.not_null();
+            }
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(Alias::new(table))
+                        .modify_column(&mut column)
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,1049 @@
+//! The adapter's database operations, independent of the HTTP API.
+//!
+//! The route handlers delegate to [`AuthAdapter`], so the same logic, token
+//! encryption included, can be driven from another program or tested without
+//! the router.
+
+use std::{
+    future::Future,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use chrono::Utc;
+use email_address::EmailAddress;
+use entities::{account, session, user, verification_token};
+use rand::{distributions::Alphanumeric, Rng};
+use sea_orm::{
+    prelude::{DateTimeUtc, DateTimeWithTimeZone},
+    sea_query::{Expr, Func, LikeExpr, OnConflict},
+    ActiveModelTrait, ColumnTrait, Condition, ConnectionTrait, DatabaseConnection, DbErr,
+    EntityTrait, ModelTrait, PaginatorTrait, QueryFilter, QueryOrder, QuerySelect, Set,
+    TransactionTrait,
+};
+use serde::{Deserialize, Deserializer, Serialize};
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::{
+    error::{is_disconnect, AppError},
+    token_encryption::{open_account, seal_account, seal_field, TokenCipher},
+};
+
+/// Runs a database operation, and runs it once more if the connection dropped
+/// underneath it. The pool discards broken connections, so the retry gets a
+/// fresh one.
+///
+/// Only for operations that are safe to repeat: plain reads, and
+/// [`find_session`], whose one write deletes an expired session. If that
+/// delete did commit before the connection dropped, the retry finds no session
+/// and reports a miss. Either way the caller gets `null` and the row is gone.
+async fn retry_on_disconnect<T, F, Fut>(mut operation: F) -> Result<T, DbErr>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, DbErr>>,
+{
+    match operation().await {
+        Err(e) if is_disconnect(&e) => {
+            warn!("lost the database connection, retrying once: {e}");
+            operation().await
+        }
+        result => result,
+    }
+}
+
+pub(crate) fn validate_email(email: &str) -> Result<(), AppError> {
+    if EmailAddress::is_valid(email) {
+        Ok(())
+    } else {
+        Err(AppError::Validation(format!(
+            "invalid email address: {email}"
+        )))
+    }
+}
+
+/// The fields a client may set when creating a user. The `id` and timestamps
+/// are assigned by the adapter.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NewUser {
+    pub name: Option<String>,
+    pub email: Option<String>,
+    pub email_verified: Option<DateTimeWithTimeZone>,
+    pub image: Option<String>,
+    pub locale: Option<String>,
+    pub timezone: Option<String>,
+    pub handle: Option<String>,
+}
+
+impl NewUser {
+    /// Rejects an invalid email address.
+    pub fn validate(&self) -> Result<(), AppError> {
+        match &self.email {
+            Some(email) => validate_email(email),
+            None => Ok(()),
+        }
+    }
+
+    /// Validates the user and builds the row to insert under a fresh `id`.
+    pub(crate) fn into_active_model(self) -> Result<user::ActiveModel, AppError> {
+        self.validate()?;
+        Ok(user::ActiveModel {
+            id: Set(Uuid::new_v4().to_string()),
+            name: Set(self.name),
+            email: Set(self.email.map(|email| email.to_lowercase())),
+            email_verified: Set(self.email_verified),
+            image: Set(self.image),
+            locale: Set(self.locale),
+            timezone: Set(self.timezone),
+            handle: Set(self.handle),
+            ..Default::default()
+        })
+    }
+}
+
+/// The outcome of [`AuthAdapter::create_users`].
+#[derive(Debug)]
+pub struct CreatedUsers {
+    /// `false` if an atomic batch was rolled back.
+    pub committed: bool,
+    /// One result per user, in order. A rolled back batch ends with the
+    /// failure that stopped it.
+    pub results: Vec<Result<user::Model, AppError>>,
+}
+
+/// Narrows [`AuthAdapter::list_users`]. Unset fields match every user.
+#[derive(Debug, Default)]
+pub struct UserFilter {
+    /// Users whose name or email contains this, ignoring case.
+    pub q: Option<String>,
+    /// Users whose email is (or isn't) verified.
+    pub email_verified: Option<bool>,
+    /// Users created at or after this time.
+    pub created_after: Option<DateTimeWithTimeZone>,
+    /// Users created before this time.
+    pub created_before: Option<DateTimeWithTimeZone>,
+}
+
+impl UserFilter {
+    fn condition(&self) -> Condition {
+        let mut filters = Condition::all();
+        if let Some(q) = self.q.as_deref().map(str::trim).filter(|q| !q.is_empty()) {
+            let pattern = format!("%{}%", escape_like(&q.to_lowercase()));
+            let contains = |column: user::Column| {
+                Expr::expr(Func::lower(Expr::col((user::Entity, column))))
+                    .like(LikeExpr::new(pattern.clone()).escape('\\'))
+            };
+            filters = filters.add(
+                Condition::any()
+                    .add(contains(user::Column::Name))
+                    .add(contains(user::Column::Email)),
+            );
+        }
+        match self.email_verified {
+            Some(true) => filters = filters.add(user::Column::EmailVerified.is_not_null()),
+            Some(false) => filters = filters.add(user::Column::EmailVerified.is_null()),
+            None => {}
+        }
+        if let Some(created_after) = self.created_after {
+            filters = filters.add(user::Column::CreatedAt.gte(created_after));
+        }
+        if let Some(created_before) = self.created_before {
+            filters = filters.add(user::Column::CreatedAt.lt(created_before));
+        }
+        filters
+    }
+}
+
+/// Escapes `LIKE` wildcards so user input only matches literally.
+fn escape_like(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, '\\' | '%' | '_') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Fields that can be changed on an existing user. Omitted fields are left as they are.
+#[derive(Debug, Default, Deserialize)]
+pub struct UserUpdate {
+    pub name: Option<String>,
+    pub email: Option<String>,
+    /// Absent leaves it as is, `null` clears it so the email must be verified again.
+    #[serde(default, deserialize_with = "present")]
+    pub email_verified: Option<Option<DateTimeWithTimeZone>>,
+    pub image: Option<String>,
+    /// Like `email_verified`, the profile fields below are cleared by `null`.
+    #[serde(default, deserialize_with = "present")]
+    pub locale: Option<Option<String>>,
+    #[serde(default, deserialize_with = "present")]
+    pub timezone: Option<Option<String>>,
+    #[serde(default, deserialize_with = "present")]
+    pub handle: Option<Option<String>>,
+}
+
+/// Deserializes a field that is present in the body, so `null` becomes
+/// `Some(None)` instead of being indistinguishable from an absent field.
+fn present<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+/// A user together with their linked accounts and active sessions.
+#[derive(Serialize)]
+pub struct UserWithRelations {
+    pub user: user::Model,
+    /// OAuth tokens are always blanked.
+    pub accounts: Vec<AccountWithExpiry>,
+    /// Only sessions that haven't expired, latest expiry first.
+    pub sessions: Vec<session::Model>,
+}
+
+/// An account along with whether its OAuth access token has expired.
+#[derive(Serialize)]
+pub struct AccountWithExpiry {
+    #[serde(flatten)]
+    pub account: account::Model,
+    /// `true` once `expires_at` has passed. Accounts without an expiry never expire.
+    pub is_expired: bool,
+}
+
+impl From<account::Model> for AccountWithExpiry {
+    fn from(account: account::Model) -> Self {
+        let is_expired = account
+            .expires_at
+            .is_some_and(|expires_at| i64::from(expires_at) <= Utc::now().timestamp());
+        AccountWithExpiry {
+            account,
+            is_expired,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct UserAndSession {
+    pub user: user::Model,
+    pub session: session::Model,
+}
+
+/// Refreshed OAuth token fields. Omitted fields are left as they are.
+#[derive(Debug, Default, Deserialize)]
+pub struct AccountTokenUpdate {
+    pub access_token: Option<String>,
+    pub refresh_token: Option<String>,
+    pub expires_at: Option<i32>,
+    pub scope: Option<String>,
+}
+
+/// Blanks an account's OAuth tokens for listings that don't need them.
+fn without_secrets(mut account: account::Model) -> account::Model {
+    account.access_token = None;
+    account.refresh_token = None;
+    account.id_token = None;
+    account
+}
+
+/// The outcome of looking a session up by its token.
+pub enum SessionLookup {
+    Hit(Box<UserAndSession>),
+    Miss,
+    /// The session had expired. It has been deleted, unless it was looked up
+    /// with [`AuthAdapter::get_session_and_user_read_only`].
+    Expired,
+}
+
+/// Fields that can be changed on an existing session. Omitted fields are left as they are.
+///
+/// `expires` is an RFC 3339 string, which must carry an offset, or Unix epoch
+/// seconds or milliseconds. It is converted to UTC before it is stored.
+#[derive(Debug, Default, Deserialize)]
+pub struct SessionUpdate {
+    pub session_token: Option<String>,
+    #[serde(default, deserialize_with = "entities::id::deserialize_option")]
+    pub user_id: Option<String>,
+    #[serde(default, deserialize_with = "entities::timestamp::deserialize_option")]
+    pub expires: Option<DateTimeUtc>,
+}
+
+/// Length of generated session tokens, in alphanumeric characters (~238 bits).
+const SESSION_TOKEN_LEN: usize = 40;
+
+/// Generates a session token from the thread-local CSPRNG.
+fn generate_session_token() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(SESSION_TOKEN_LEN)
+        .map(char::from)
+        .collect()
+}
+
+/// Operations taking longer than this many milliseconds are logged unless
+/// configured otherwise. Also the default for `SLOW_QUERY_MS`.
+pub const DEFAULT_SLOW_OPERATION_MS: u64 = 200;
+
+/// Logs a warning when dropped if the operation it was started for has run
+/// longer than the threshold. Dropping it also covers errors and cancellation.
+struct OperationTimer {
+    operation: &'static str,
+    started: Instant,
+    threshold: Duration,
+}
+
+impl Drop for OperationTimer {
+    fn drop(&mut self) {
+        let elapsed = self.started.elapsed();
+        if elapsed > self.threshold {
+            warn!(
+                "slow database operation: {} took {}ms",
+                self.operation,
+                elapsed.as_millis()
+            );
+        }
+    }
+}
+
+/// Auth operations on one database.
+#[derive(Clone)]
+pub struct AuthAdapter {
+    conn: Arc<DatabaseConnection>,
+    slow_operation: Duration,
+    token_cipher: Option<Arc<TokenCipher>>,
+    single_active_token: bool,
+}
+
+impl AuthAdapter {
+    pub fn new(conn: Arc<DatabaseConnection>) -> Self {
+        Self {
+            conn,
+            slow_operation: Duration::from_millis(DEFAULT_SLOW_OPERATION_MS),
+            token_cipher: None,
+            single_active_token: false,
+        }
+    }
+
+    /// Logs a warning for operations slower than `threshold`. Defaults to
+    /// [`DEFAULT_SLOW_OPERATION_MS`].
+    pub fn with_slow_operation_threshold(mut self, threshold: Duration) -> Self {
+        self.slow_operation = threshold;
+        self
+    }
+
+    /// Encrypts accounts' OAuth tokens at rest with `cipher`.
+    pub fn with_token_cipher(mut self, cipher: Arc<TokenCipher>) -> Self {
+        self.token_cipher = Some(cipher);
+        self
+    }
+
+    /// Keeps only the newest verification token per identifier: creating a
+    /// token deletes the identifier's earlier ones.
+    pub fn with_single_active_token(mut self, single_active_token: bool) -> Self {
+        self.single_active_token = single_active_token;
+        self
+    }
+
+    fn cipher(&self) -> Option<&TokenCipher> {
+        self.token_cipher.as_deref()
+    }
+
+    /// Runs one adapter operation, logging it if slow and counting it in
+    /// `db_errors_total` if the database failed it.
+    async fn timed<T>(
+        &self,
+        operation: &'static str,
+        body: impl Future<Output = Result<T, AppError>>,
+    ) -> Result<T, AppError> {
+        let _timer = OperationTimer {
+            operation,
+            started: Instant::now(),
+            threshold: self.slow_operation,
+        };
+        let result = body.await;
+        // a lost connection surfaces as `Unavailable` but is a database failure too
+        if let Err(AppError::Database(_) | AppError::Unavailable(_)) = &result {
+            metrics::increment_counter!("db_errors_total", "operation" => operation);
+        }
+        result
+    }
+
+    /// Creates a user, rejecting an invalid email address. The email is
+    /// stored lowercased.
+    pub async fn create_user(&self, user: NewUser) -> Result<user::Model, AppError> {
+        self.timed("create_user", async {
+            insert_user(user, &*self.conn).await
+        })
+        .await
+    }
+
+    /// Creates many users at once. With `atomic`, the first failure rolls back
+    /// every insert; otherwise failed users are reported and the rest are
+    /// still inserted.
+    pub async fn create_users(
+        &self,
+        users: Vec<NewUser>,
+        atomic: bool,
+    ) -> Result<CreatedUsers, AppError> {
+        self.timed("create_users", async {
+            let mut results = Vec::with_capacity(users.len());
+            if atomic {
+                let txn = self.conn.begin().await?;
+                for user in users {
+                    let result = insert_user(user, &txn).await;
+                    let failed = result.is_err();
+                    results.push(result);
+                    if failed {
+                        // dropping the transaction rolls back the earlier inserts
+                        return Ok(CreatedUsers {
+                            committed: false,
+                            results,
+                        });
+                    }
+                }
+                txn.commit().await?;
+            } else {
+                for user in users {
+                    results.push(insert_user(user, &*self.conn).await);
+                }
+            }
+            Ok(CreatedUsers {
+                committed: true,
+                results,
+            })
+        })
+        .await
+    }
+
+    pub async fn get_user(&self, id: &str) -> Result<Option<user::Model>, AppError> {
+        self.timed("get_user", async {
+            Ok(retry_on_disconnect(|| user::Entity::find_by_id(id).one(&*self.conn)).await?)
+        })
+        .await
+    }
+
+    /// Finds a user along with their accounts, tokens blanked, and their
+    /// unexpired sessions.
+    pub async fn get_user_with_relations(
+        &self,
+        id: &str,
+    ) -> Result<Option<UserWithRelations>, AppError> {
+        self.timed("get_user_with_relations", async {
+            let with_accounts = user::Entity::find_by_id(id)
+                .find_with_related(account::Entity)
+                .order_by_asc(account::Column::Provider);
+            let Some((user, accounts)) =
+                retry_on_disconnect(|| with_accounts.clone().all(&*self.conn))
+                    .await?
+                    .into_iter()
+                    .next()
+            else {
+                return Ok(None);
+            };
+            // the filter drops the user row when nothing matches, leaving no sessions
+            let with_sessions = user::Entity::find_by_id(id)
+                .find_with_related(session::Entity)
+                .filter(session::Column::Expires.gt(Utc::now()))
+                .order_by_desc(session::Column::Expires);
+            let sessions = retry_on_disconnect(|| with_sessions.clone().all(&*self.conn))
+                .await?
+                .into_iter()
+                .next()
+                .map(|(_, sessions)| sessions)
+                .unwrap_or_default();
+            let accounts = accounts
+                .into_iter()
+                .map(|account| without_secrets(account).into())
+                .collect();
+            Ok(Some(UserWithRelations {
+                user,
+                accounts,
+                sessions,
+            }))
+        })
+        .await
+    }
+
+    /// Counts the users matching `filter`.
+    pub async fn count_users(&self, filter: &UserFilter) -> Result<u64, AppError> {
+        self.timed("count_users", async {
+            let select = user::Entity::find().filter(filter.condition());
+            Ok(retry_on_disconnect(|| select.clone().count(&*self.conn)).await?)
+        })
+        .await
+    }
+
+    /// Lists up to `limit` users matching `filter` in `id` order, starting
+    /// after the user with id `after` when given.
+    pub async fn list_users(
+        &self,
+        filter: &UserFilter,
+        after: Option<&str>,
+        limit: u64,
+    ) -> Result<Vec<user::Model>, AppError> {
+        self.timed("list_users", async {
+            let mut select = user::Entity::find()
+                .filter(filter.condition())
+                .order_by_asc(user::Column::Id);
+            if let Some(after) = after {
+                select = select.filter(user::Column::Id.gt(after));
+            }
+            let select = select.limit(limit);
+            Ok(retry_on_disconnect(|| select.clone().all(&*self.conn)).await?)
+        })
+        .await
+    }
+
+    /// The `after` to pass to [`list_users`](Self::list_users) for the page
+    /// before the one starting after `cursor`. `None` means that page is the
+    /// first.
+    pub async fn previous_page_cursor(
+        &self,
+        filter: &UserFilter,
+        cursor: &str,
+        limit: u64,
+    ) -> Result<Option<String>, AppError> {
+        self.timed("previous_page_cursor", async {
+            // the previous page holds the `limit` users up to and including the cursor
+            let select = user::Entity::find()
+                .filter(filter.condition())
+                .filter(user::Column::Id.lte(cursor))
+                .order_by_desc(user::Column::Id)
+                .limit(limit + 1);
+            let before = retry_on_disconnect(|| select.clone().all(&*self.conn)).await?;
+            Ok(before.into_iter().nth(limit as usize).map(|user| user.id))
+        })
+        .await
+    }
+
+    /// Applies `update` to the user with `id`, rejecting an invalid email
+    /// address. Returns the user as persisted.
+    pub async fn update_user(&self, id: &str, update: UserUpdate) -> Result<user::Model, AppError> {
+        self.timed("update_user", async {
+            let mut user: user::ActiveModel = user::Entity::find_by_id(id)
+                .one(&*self.conn)
+                .await?
+                .ok_or(AppError::NotFound)?
+                .into();
+            if let Some(name) = update.name {
+                user.name = Set(Some(name));
+            }
+            if let Some(email) = update.email {
+                validate_email(&email)?;
+                user.email = Set(Some(email.to_lowercase()));
+            }
+            if let Some(email_verified) = update.email_verified {
+                user.email_verified = Set(email_verified);
+            }
+            if let Some(image) = update.image {
+                user.image = Set(Some(image));
+            }
+            if let Some(locale) = update.locale {
+                user.locale = Set(locale);
+            }
+            if let Some(timezone) = update.timezone {
+                user.timezone = Set(timezone);
+            }
+            if let Some(handle) = update.handle {
+                user.handle = Set(handle);
+            }
+            // `update` reads the row back, so the result reflects what was persisted
+            Ok(user.update(&*self.conn).await?)
+        })
+        .await
+    }
+
+    /// Deletes the user with `id`, and with it their accounts and sessions,
+    /// returning the deleted row.
+    ///
+    /// Runs on `conn`, so the caller can make it part of its own transaction.
+    pub async fn delete_user<C: ConnectionTrait>(
+        &self,
+        id: &str,
+        conn: &C,
+    ) -> Result<user::Model, AppError> {
+        self.timed("delete_user", async {
+            let user = user::Entity::find_by_id(id)
+                .one(conn)
+                .await?
+                .ok_or(AppError::NotFound)?;
+            user.clone().delete(conn).await?;
+            Ok(user)
+        })
+        .await
+    }
+
+    /// Finds a user by email, ignoring case.
+    pub async fn get_user_by_email(&self, email: &str) -> Result<Option<user::Model>, AppError> {
+        self.timed("get_user_by_email", async {
+            let email = email.to_lowercase();
+            let user = retry_on_disconnect(|| {
+                user::Entity::find()
+                    .filter(user::Column::Email.eq(email.as_str()))
+                    .one(&*self.conn)
+            })
+            .await?;
+            Ok(user)
+        })
+        .await
+    }
+
+    /// Finds the user a provider account is linked to.
+    pub async fn get_user_by_account(
+        &self,
+        provider: &str,
+        provider_account_id: &str,
+    ) -> Result<Option<user::Model>, AppError> {
+        self.timed("get_user_by_account", async {
+            let user = retry_on_disconnect(|| {
+                user::Entity::find()
+                    .inner_join(account::Entity)
+                    .filter(account::Column::Provider.eq(provider))
+                    .filter(account::Column::ProviderAccountId.eq(provider_account_id))
+                    .one(&*self.conn)
+            })
+            .await?;
+            Ok(user)
+        })
+        .await
+    }
+
+    /// Finds the users of the first account matching `provider_account_id`
+    /// and `provider`, whichever are given.
+    pub async fn get_users_by_account(
+        &self,
+        provider_account_id: Option<&str>,
+        provider: Option<&str>,
+    ) -> Result<Option<Vec<user::Model>>, AppError> {
+        self.timed("get_users_by_account", async {
+            let mut condition = Condition::all();
+            if let Some(provider_account_id) = provider_account_id {
+                condition =
+                    condition.add(account::Column::ProviderAccountId.eq(provider_account_id));
+            }
+            if let Some(provider) = provider {
+                condition = condition.add(account::Column::Provider.eq(provider));
+            }
+            let select = account::Entity::find()
+                .filter(condition)
+                .find_with_related(user::Entity);
+            let users = retry_on_disconnect(|| select.clone().all(&*self.conn))
+                .await?
+                .into_iter()
+                .next()
+                .map(|(_, users)| users);
+            Ok(users)
+        })
+        .await
+    }
+
+    /// Links a provider account, or refreshes its tokens when the same user
+    /// links it again. The upsert is a single statement, so concurrent OAuth
+    /// callbacks for one account can't race between a lookup and the insert.
+    ///
+    /// A refreshed link keeps its original `id`, so comparing it with the
+    /// given one tells the two cases apart.
+    pub async fn link_account(&self, account: account::Model) -> Result<account::Model, AppError> {
+        self.timed("link_account", async {
+            let user_id = account.user_id.clone();
+            let provider = account.provider.clone();
+            let provider_account_id = account.provider_account_id.clone();
+            let upsert = OnConflict::columns([
+                account::Column::Provider,
+                account::Column::ProviderAccountId,
+            ])
+            .update_columns([
+                account::Column::AccessToken,
+                account::Column::RefreshToken,
+                account::Column::ExpiresAt,
+                account::Column::TokenType,
+                account::Column::Scope,
+                account::Column::IdToken,
+                account::Column::SessionState,
+            ])
+            // linking someone else's identity would let this user sign in as them
+            .action_and_where(account::Column::UserId.eq(user_id.as_str()))
+            .to_owned();
+
+            let txn = self.conn.begin().await?;
+            let item: account::ActiveModel = seal_account(self.cipher(), account).into();
+            match account::Entity::insert(item)
+                .on_conflict(upsert)
+                .exec(&txn)
+                .await
+            {
+                Ok(_) => {}
+                // the row exists but the condition above kept it from being updated
+                Err(DbErr::RecordNotInserted) => return Err(AppError::AccountAlreadyLinked),
+                Err(e) => return Err(e.into()),
+            }
+            let account = account::Entity::find()
+                .filter(account::Column::Provider.eq(provider))
+                .filter(account::Column::ProviderAccountId.eq(provider_account_id))
+                .one(&txn)
+                .await?
+                .ok_or(AppError::NotFound)?;
+            // MySQL has no conditional upsert, so the owner is checked again before committing
+            if account.user_id != user_id {
+                return Err(AppError::AccountAlreadyLinked);
+            }
+            txn.commit().await?;
+            open_account(self.cipher(), account)
+        })
+        .await
+    }
+
+    /// Finds a provider account, with its tokens decrypted.
+    pub async fn get_account(
+        &self,
+        provider: &str,
+        provider_account_id: &str,
+    ) -> Result<Option<AccountWithExpiry>, AppError> {
+        self.timed("get_account", async {
+            let select = account::Entity::find()
+                .filter(account::Column::Provider.eq(provider))
+                .filter(account::Column::ProviderAccountId.eq(provider_account_id));
+            retry_on_disconnect(|| select.clone().one(&*self.conn))
+                .await?
+                .map(|account| Ok(open_account(self.cipher(), account)?.into()))
+                .transpose()
+        })
+        .await
+    }
+
+    /// Lists a user's accounts by provider. OAuth tokens are blanked unless
+    /// `include_secrets` is set.
+    pub async fn list_user_accounts(
+        &self,
+        user_id: &str,
+        include_secrets: bool,
+    ) -> Result<Vec<AccountWithExpiry>, AppError> {
+        self.timed("list_user_accounts", async {
+            let select = account::Entity::find()
+                .filter(account::Column::UserId.eq(user_id))
+                .order_by_asc(account::Column::Provider);
+            retry_on_disconnect(|| select.clone().all(&*self.conn))
+                .await?
+                .into_iter()
+                .map(|account| {
+                    let account = if include_secrets {
+                        account
+                    } else {
+                        without_secrets(account)
+                    };
+                    Ok(open_account(self.cipher(), account)?.into())
+                })
+                .collect()
+        })
+        .await
+    }
+
+    /// Stores refreshed OAuth tokens on a provider account.
+    pub async fn update_account_tokens(
+        &self,
+        provider: &str,
+        provider_account_id: &str,
+        update: AccountTokenUpdate,
+    ) -> Result<account::Model, AppError> {
+        self.timed("update_account_tokens", async {
+            let mut account: account::ActiveModel = account::Entity::find()
+                .filter(account::Column::Provider.eq(provider))
+                .filter(account::Column::ProviderAccountId.eq(provider_account_id))
+                .one(&*self.conn)
+                .await?
+                .ok_or(AppError::NotFound)?
+                .into();
+            let cipher = self.cipher();
+            if let Some(access_token) = update.access_token {
+                account.access_token = Set(seal_field(cipher, "access_token", Some(access_token)));
+            }
+            if let Some(refresh_token) = update.refresh_token {
+                account.refresh_token =
+                    Set(seal_field(cipher, "refresh_token", Some(refresh_token)));
+            }
+            if let Some(expires_at) = update.expires_at {
+                account.expires_at = Set(Some(expires_at));
+            }
+            if let Some(scope) = update.scope {
+                account.scope = Set(Some(scope));
+            }
+            open_account(cipher, account.update(&*self.conn).await?)
+        })
+        .await
+    }
+
+    pub async fn delete_account(
+        &self,
+        provider: &str,
+        provider_account_id: &str,
+    ) -> Result<(), AppError> {
+        self.timed("delete_account", async {
+            let result = account::Entity::delete_many()
+                .filter(account::Column::Provider.eq(provider))
+                .filter(account::Column::ProviderAccountId.eq(provider_account_id))
+                .exec(&*self.conn)
+                .await?;
+            if result.rows_affected == 0 {
+                return Err(AppError::NotFound);
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    /// Unlinks a provider account only if it belongs to `user_id`. An account
+    /// linked to someone else is [`AppError::NotFound`].
+    pub async fn unlink_account(
+        &self,
+        user_id: &str,
+        provider: &str,
+        provider_account_id: &str,
+    ) -> Result<(), AppError> {
+        self.timed("unlink_account", async {
+            let result = account::Entity::delete_many()
+                .filter(account::Column::UserId.eq(user_id))
+                .filter(account::Column::Provider.eq(provider))
+                .filter(account::Column::ProviderAccountId.eq(provider_account_id))
+                .exec(&*self.conn)
+                .await?;
+            if result.rows_affected == 0 {
+                return Err(AppError::NotFound);
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    pub async fn create_session(
+        &self,
+        session: session::Model,
+    ) -> Result<session::Model, AppError> {
+        self.timed("create_session", async {
+            let item: session::ActiveModel = session.into();
+            match item.insert(&*self.conn).await.map_err(AppError::from) {
+                // callers generate tokens, so tell them to pick a new one
+                Err(AppError::Conflict(_)) => Err(AppError::Conflict(
+                    "session token already exists".to_owned(),
+                )),
+                result => result,
+            }
+        })
+        .await
+    }
+
+    /// Finds a session and its user by the session token. An expired session
+    /// is deleted as it is read, so the table heals itself.
+    pub async fn get_session_and_user(&self, token: &str) -> Result<SessionLookup, AppError> {
+        self.timed("get_session_and_user", async {
+            Ok(retry_on_disconnect(|| find_session(&self.conn, token, true)).await?)
+        })
+        .await
+    }
+
+    /// Like [`get_session_and_user`](Self::get_session_and_user), but leaves an
+    /// expired session in place, for when the database mustn't be written to.
+    pub async fn get_session_and_user_read_only(
+        &self,
+        token: &str,
+    ) -> Result<SessionLookup, AppError> {
+        self.timed("get_session_and_user", async {
+            Ok(retry_on_disconnect(|| find_session(&self.conn, token, false)).await?)
+        })
+        .await
+    }
+
+    /// Updates the session with token `token`. An empty `session_token` is
+    /// ignored. With `rotate` the session gets a fresh random token instead,
+    /// so a leaked copy of the old one stops working.
+    pub async fn update_session(
+        &self,
+        token: &str,
+        update: SessionUpdate,
+        rotate: bool,
+    ) -> Result<session::Model, AppError> {
+        self.timed("update_session", async {
+            let txn = self.conn.begin().await?;
+            let mut session: session::ActiveModel = session::Entity::find()
+                .filter(session::Column::SessionToken.eq(token))
+                .one(&txn)
+                .await?
+                .ok_or(AppError::NotFound)?
+                .into();
+            if rotate {
+                session.session_token = Set(generate_session_token());
+            } else if let Some(session_token) =
+                update.session_token.filter(|token| !token.is_empty())
+            {
+                session.session_token = Set(session_token);
+            }
+            if let Some(user_id) = update.user_id {
+                session.user_id = Set(user_id);
+            }
+            if let Some(expires) = update.expires {
+                session.expires = Set(expires);
+            }
+            let session = session.update(&txn).await?;
+            txn.commit().await?;
+            Ok(session)
+        })
+        .await
+    }
+
+    /// Deletes a session by its token, returning what was deleted.
+    pub async fn delete_session(&self, token: &str) -> Result<session::Model, AppError> {
+        self.timed("delete_session", async {
+            let session = session::Entity::find()
+                .filter(session::Column::SessionToken.eq(token))
+                .one(&*self.conn)
+                .await?
+                .ok_or(AppError::NotFound)?;
+            session.clone().delete(&*self.conn).await?;
+            Ok(session)
+        })
+        .await
+    }
+
+    /// Lists a user's unexpired sessions, latest expiry first.
+    pub async fn list_user_sessions(&self, user_id: &str) -> Result<Vec<session::Model>, AppError> {
+        self.timed("list_user_sessions", async {
+            let select = session::Entity::find()
+                .filter(session::Column::UserId.eq(user_id))
+                .filter(session::Column::Expires.gt(Utc::now()))
+                .order_by_desc(session::Column::Expires);
+            Ok(retry_on_disconnect(|| select.clone().all(&*self.conn)).await?)
+        })
+        .await
+    }
+
+    /// Deletes every session of the user with `user_id`, except the one with
+    /// `except_token` when given. Returns how many were deleted.
+    ///
+    /// Runs on `conn`, so the caller can make it part of its own transaction.
+    pub async fn revoke_sessions<C: ConnectionTrait>(
+        &self,
+        user_id: &str,
+        except_token: Option<&str>,
+        conn: &C,
+    ) -> Result<u64, AppError> {
+        self.timed("revoke_sessions", async {
+            let mut condition = Condition::all().add(session::Column::UserId.eq(user_id));
+            if let Some(token) = except_token {
+                condition = condition.add(session::Column::SessionToken.ne(token));
+            }
+            let result = session::Entity::delete_many()
+                .filter(condition)
+                .exec(conn)
+                .await?;
+            Ok(result.rows_affected)
+        })
+        .await
+    }
+
+    /// Stores a verification token, returning the row with its server-set
+    /// `created_at`. See [`with_single_active_token`](Self::with_single_active_token).
+    pub async fn create_verification_token(
+        &self,
+        token: verification_token::Model,
+    ) -> Result<verification_token::Model, AppError> {
+        self.timed("create_verification_token", async {
+            let txn = self.conn.begin().await?;
+            if self.single_active_token {
+                // only the newest magic link for an identifier stays valid
+                verification_token::Entity::delete_many()
+                    .filter(verification_token::Column::Identifier.eq(&token.identifier))
+                    .exec(&txn)
+                    .await?;
+            }
+            let item: verification_token::ActiveModel = token.into();
+            let created = item.insert(&txn).await?;
+            txn.commit().await?;
+            Ok(created)
+        })
+        .await
+    }
+
+    /// Deletes the verification token issued to `identifier`, returning what
+    /// was deleted.
+    pub async fn delete_verification_token(
+        &self,
+        identifier: &str,
+    ) -> Result<verification_token::Model, AppError> {
+        self.timed("delete_verification_token", async {
+            let verif_token = verification_token::Entity::find()
+                .filter(verification_token::Column::Identifier.eq(identifier))
+                .one(&*self.conn)
+                .await?
+                .ok_or(AppError::NotFound)?;
+            verif_token.clone().delete(&*self.conn).await?;
+            Ok(verif_token)
+        })
+        .await
+    }
+
+    /// Consumes a verification token. Both fields must match, and an expired
+    /// token is [`AppError::Gone`].
+    pub async fn use_verification_token(
+        &self,
+        identifier: &str,
+        token: &str,
+    ) -> Result<verification_token::Model, AppError> {
+        self.timed("use_verification_token", async {
+            let txn = self.conn.begin().await?;
+            let verif_token = verification_token::Entity::find()
+                .filter(verification_token::Column::Identifier.eq(identifier))
+                .filter(verification_token::Column::Token.eq(token))
+                .one(&txn)
+                .await?
+                .ok_or(AppError::NotFound)?;
+            // a concurrent request may have consumed the token since we read it
+            let result = verification_token::Entity::delete_by_id(verif_token.id)
+                .exec(&txn)
+                .await?;
+            if result.rows_affected == 0 {
+                return Err(AppError::NotFound);
+            }
+            txn.commit().await?;
+            // the token is consumed either way, but an expired one must not sign anyone in
+            if verif_token.expires < Utc::now() {
+                return Err(AppError::Gone);
+            }
+            Ok(verif_token)
+        })
+        .await
+    }
+}
+
+async fn insert_user<C: ConnectionTrait>(user: NewUser, conn: &C) -> Result<user::Model, AppError> {
+    Ok(user.into_active_model()?.insert(conn).await?)
+}
+
+async fn find_session(
+    conn: &DatabaseConnection,
+    token: &str,
+    delete_expired: bool,
+) -> Result<SessionLookup, DbErr> {
+    let txn = conn.begin().await?;
+    let Some((session, Some(user))) = session::Entity::find()
+        .filter(session::Column::SessionToken.eq(token))
+        .find_also_related(user::Entity)
+        .one(&txn)
+        .await?
+    else {
+        return Ok(SessionLookup::Miss);
+    };
+    if session.expires < Utc::now() {
+        if delete_expired {
+            session.delete(&txn).await?;
+        }
+        txn.commit().await?;
+        return Ok(SessionLookup::Expired);
+    }
+    txn.commit().await?;
+    Ok(SessionLookup::Hit(Box::new(UserAndSession {
+        user,
+        session,
+    })))
+}
@@ -0,0 +1,35 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::State,
+    http::{header::AUTHORIZATION, Request},
+    middleware::Next,
+    response::Response,
+};
+use subtle::ConstantTimeEq;
+
+use crate::error::AppError;
+
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// Rejects requests that don't carry the shared adapter secret, either as
+/// `X-API-Key: <secret>` or `Authorization: Bearer <secret>`.
+pub async fn require_api_key<B>(
+    State(secret): State<Arc<str>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Result<Response, AppError> {
+    let headers = request.headers();
+    let provided = headers
+        .get(API_KEY_HEADER)
+        .or_else(|| headers.get(AUTHORIZATION))
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.strip_prefix("Bearer ").unwrap_or(value));
+
+    match provided {
+        Some(key) if bool::from(key.as_bytes().ct_eq(secret.as_bytes())) => {
+            Ok(next.run(request).await)
+        }
+        _ => Err(AppError::Unauthorized),
+    }
+}
@@ -0,0 +1,35 @@
+use std::{sync::Arc, time::Duration};
+
+use chrono::Utc;
+use entities::session;
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
+use tokio::time;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
+
+/// Deletes expired sessions every `period` until `shutdown` is cancelled.
+///
+/// Cancellation is only observed between runs, so a delete that has started
+/// always completes.
+pub async fn prune_expired_sessions(
+    conn: Arc<DatabaseConnection>,
+    period: Duration,
+    shutdown: CancellationToken,
+) {
+    let mut interval = time::interval(period);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {},
+            _ = shutdown.cancelled() => break,
+        }
+
+        match session::Entity::delete_many()
+            .filter(session::Column::Expires.lt(Utc::now()))
+            .exec(&*conn)
+            .await
+        {
+            Ok(result) => info!("pruned {} expired sessions", result.rows_affected),
+            Err(e) => error!("failed to prune expired sessions: {e}"),
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+};
+
+use axum::{extract::ConnectInfo, http::Request};
+
+/// An address block such as `10.0.0.0/8`. A bare address is a block of one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                masked(u32::from(net).into(), self.prefix, 32)
+                    == masked(u32::from(ip).into(), self.prefix, 32)
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                masked(net.into(), self.prefix, 128) == masked(ip.into(), self.prefix, 128)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Keeps the top `prefix` bits of a `width`-bit address.
+fn masked(bits: u128, prefix: u8, width: u8) -> u128 {
+    let host_bits = u32::from(width - prefix);
+    bits.checked_shr(host_bits).unwrap_or(0)
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|_| format!("invalid address in {s}"))?;
+        let width = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse()
+                .ok()
+                .filter(|prefix| *prefix <= width)
+                .ok_or_else(|| format!("invalid prefix length in {s}"))?,
+            None => width,
+        };
+        Ok(Cidr { addr, prefix })
+    }
+}
+
+/// The proxies allowed to report the client address in `X-Forwarded-For`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TrustedProxies {
+    cidrs: Vec<Cidr>,
+    /// Whether connections without a peer address, such as those on a Unix
+    /// socket, come from a proxy.
+    unix_socket: bool,
+}
+
+impl TrustedProxies {
+    /// Parses a comma-separated list such as `TRUSTED_PROXIES`, skipping
+    /// invalid entries. The entry `unix` trusts connections without a peer
+    /// address.
+    pub fn parse(list: &str) -> Self {
+        let mut proxies = Self::default();
+        for entry in list.split(',').map(str::trim) {
+            if entry.is_empty() {
+                continue;
+            }
+            if entry.eq_ignore_ascii_case("unix") {
+                proxies.unix_socket = true;
+                continue;
+            }
+            match entry.parse() {
+                Ok(cidr) => proxies.cidrs.push(cidr),
+                Err(e) => tracing::warn!("ignoring TRUSTED_PROXIES entry: {e}"),
+            }
+        }
+        proxies
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        self.cidrs.iter().any(|cidr| cidr.contains(ip))
+    }
+}
+
+/// The address of the client that sent `request`.
+///
+/// `X-Forwarded-For` is only believed when the peer is a trusted proxy. The
+/// chain is then walked from the right and the first hop that isn't a trusted
+/// proxy is the client, since anything to its left could have been forged.
+/// Connections without a peer address (a Unix socket) are only trusted when
+/// `TRUSTED_PROXIES` lists `unix`; otherwise their client is unknown.
+pub fn client_ip<B>(request: &Request<B>, trusted: &TrustedProxies) -> Option<IpAddr> {
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    match peer {
+        Some(peer) if !trusted.contains(peer) => return Some(peer),
+        None if !trusted.unix_socket => return None,
+        _ => {}
+    }
+
+    let mut client = peer;
+    let forwarded = request
+        .headers()
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .collect::<Vec<_>>();
+    for hop in forwarded.into_iter().rev() {
+        let Ok(ip) = hop.trim().parse::<IpAddr>() else {
+            break;
+        };
+        client = Some(ip);
+        if !trusted.contains(ip) {
+            break;
+        }
+    }
+    client
+}
@@ -0,0 +1,98 @@
+use std::{str::FromStr, sync::Arc, time::Duration};
+
+use crate::{
+    adapter::DEFAULT_SLOW_OPERATION_MS,
+    client_ip::TrustedProxies,
+    security_headers::{self, SecurityHeaders},
+};
+
+/// Router settings, read once from the environment at startup.
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// Shared secret clients must send to reach the adapter endpoints.
+    pub adapter_secret: Arc<str>,
+    /// Sustained mutating requests allowed per client IP each second.
+    pub rate_limit_per_sec: u32,
+    /// Mutating requests a client IP may burst above the sustained rate.
+    pub rate_limit_burst: u32,
+    /// Proxies allowed to report the client address in `X-Forwarded-For`.
+    /// Empty means the header is ignored and the peer address is used.
+    pub trusted_proxies: Arc<TrustedProxies>,
+    /// Return the existing user instead of a 409 when `create_user` hits a duplicate email.
+    pub create_user_idempotent: bool,
+    /// Largest request body the extractors will buffer, in bytes.
+    pub max_body_bytes: usize,
+    /// Most records accepted by one bulk request.
+    pub max_bulk_size: usize,
+    /// How long a request may run before it is abandoned with a 504.
+    pub request_timeout: Duration,
+    /// Prefix the adapter routes are mounted under, e.g. `/auth`. `/health` stays at the root.
+    pub base_path: Option<String>,
+    /// How long a replayed `Idempotency-Key` returns the original response.
+    pub idempotency_ttl: Duration,
+    /// Headers such as `X-Frame-Options` added to every response.
+    pub security_headers: SecurityHeaders,
+    /// Compress responses with gzip or brotli when the client accepts it.
+    pub enable_compression: bool,
+    /// Wrap JSON responses in `{"data", "meta"}`, or `{"errors"}` on failure.
+    pub response_envelope: bool,
+    /// Log request and response bodies, with emails and tokens redacted.
+    pub log_bodies: bool,
+    /// Lifetime of sessions created without an explicit `expires`.
+    pub session_max_age: chrono::Duration,
+    /// Seconds clients may reuse a `GET /users/:id` response before revalidating.
+    pub user_cache_max_age: u64,
+    /// Delete an identifier's earlier verification tokens when a new one is created.
+    pub single_active_token: bool,
+    /// Adapter operations running longer than this are logged as warnings.
+    pub slow_query: Duration,
+}
+
+impl Config {
+    pub fn from_env() -> Self {
+        Self {
+            adapter_secret: std::env::var("ADAPTER_SECRET")
+                .expect("missing adapter secret in env")
+                .into(),
+            rate_limit_per_sec: env_or("RATE_LIMIT_PER_SEC", 10),
+            rate_limit_burst: env_or("RATE_LIMIT_BURST", 20),
+            trusted_proxies: std::env::var("TRUSTED_PROXIES")
+                .map(|list| TrustedProxies::parse(&list))
+                .unwrap_or_default()
+                .into(),
+            create_user_idempotent: env_or("CREATE_USER_IDEMPOTENT", false),
+            max_body_bytes: env_or("MAX_BODY_BYTES", 256 * 1024),
+            max_bulk_size: env_or("MAX_BULK_SIZE", 1000),
+            request_timeout: Duration::from_secs(env_or("REQUEST_TIMEOUT_SECS", 30)),
+            base_path: std::env::var("API_BASE_PATH")
+                .ok()
+                .and_then(|path| normalize_base_path(&path)),
+            idempotency_ttl: Duration::from_secs(env_or("IDEMPOTENCY_TTL_SECS", 24 * 60 * 60)),
+            security_headers: security_headers::from_env(),
+            enable_compression: env_or("ENABLE_COMPRESSION", true),
+            response_envelope: env_or("RESPONSE_ENVELOPE", false),
+            log_bodies: env_or("LOG_BODIES", false),
+            session_max_age: chrono::Duration::seconds(env_or(
+                "SESSION_MAX_AGE_SECS",
+                30 * 24 * 60 * 60,
+            )),
+            user_cache_max_age: env_or("USER_CACHE_MAX_AGE_SECS", 5),
+            single_active_token: env_or("SINGLE_ACTIVE_TOKEN", false),
+            slow_query: Duration::from_millis(env_or("SLOW_QUERY_MS", DEFAULT_SLOW_OPERATION_MS)),
+        }
+    }
+}
+
+/// Adds a leading `/` and strips trailing ones. An empty or root path means no prefix.
+fn normalize_base_path(path: &str) -> Option<String> {
+    let path = path.trim().trim_matches('/');
+    (!path.is_empty()).then(|| format!("/{path}"))
+}
+
+/// Reads `key` from the environment, falling back to `default` when unset or unparsable.
+pub fn env_or<T: FromStr>(key: &str, default: T) -> T {
+    std::env::var(key)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
@@ -0,0 +1,125 @@
+use axum::{
+    body::{self, Full},
+    http::{
+        header::{CONTENT_LENGTH, CONTENT_TYPE, LINK},
+        HeaderMap, HeaderValue, Request, StatusCode,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde_json::{json, Map, Value};
+use tracing::error;
+
+/// Wraps JSON responses for clients that expect an envelope. Only installed
+/// when `RESPONSE_ENVELOPE` is enabled.
+///
+/// Success bodies become `{"data": ..., "meta": ...}`. Pagination moves into
+/// `meta`: `total` from `x-total-count`, `links` from `Link`, and any fields
+/// next to a page's `data`, such as `next_cursor`. Errors become
+/// `{"errors": [{"status", "code", "detail"}]}`, including errors that
+/// arrive with an empty or plain text body, such as axum's `405`. Other
+/// bodies that aren't JSON are passed through untouched.
+pub async fn wrap_responses<B>(request: Request<B>, next: Next<B>) -> Response {
+    let response = next.run(request).await;
+    let is_json = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"));
+    let is_error = response.status().is_client_error() || response.status().is_server_error();
+    if !is_json && !is_error {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let bytes = match hyper::body::to_bytes(body).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            error!("failed to buffer response for the envelope: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    if !is_json {
+        let wrapped = plain_failure(&bytes, parts.status);
+        parts
+            .headers
+            .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        parts.headers.remove(CONTENT_LENGTH);
+        return Response::from_parts(parts, body::boxed(Full::from(wrapped.to_string())));
+    }
+    let Ok(value) = serde_json::from_slice::<Value>(&bytes) else {
+        return Response::from_parts(parts, body::boxed(Full::from(bytes)));
+    };
+    let wrapped = if parts.status.is_success() {
+        success(value, &parts.headers)
+    } else {
+        failure(value, parts.status)
+    };
+    parts.headers.remove(CONTENT_LENGTH);
+    Response::from_parts(parts, body::boxed(Full::from(wrapped.to_string())))
+}
+
+fn success(value: Value, headers: &HeaderMap) -> Value {
+    let mut meta = Map::new();
+    let data = match value {
+        Value::Object(mut page) if page.contains_key("data") => {
+            let data = page.remove("data").unwrap_or_default();
+            meta.extend(page);
+            data
+        }
+        value => value,
+    };
+    if let Some(total) = headers
+        .get("x-total-count")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok())
+    {
+        meta.insert("total".to_owned(), total.into());
+    }
+    let links = links(headers);
+    if !links.is_empty() {
+        meta.insert("links".to_owned(), Value::Object(links));
+    }
+    json!({ "data": data, "meta": meta })
+}
+
+/// `Link` header entries such as `<url>; rel="next"`, keyed by `rel`.
+fn links(headers: &HeaderMap) -> Map<String, Value> {
+    headers
+        .get_all(LINK)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|link| {
+            let (url, params) = link.split_once(';')?;
+            let url = url.trim().strip_prefix('<')?.strip_suffix('>')?;
+            let rel = params
+                .split(';')
+                .find_map(|param| param.trim().strip_prefix("rel="))?
+                .trim_matches('"');
+            Some((rel.to_owned(), Value::String(url.to_owned())))
+        })
+        .collect()
+}
+
+fn failure(mut value: Value, status: StatusCode) -> Value {
+    let error = json!({
+        "status": status.as_str(),
+        "code": value.get_mut("code").map(Value::take).unwrap_or_default(),
+        "detail": value.get_mut("error").map(Value::take).unwrap_or_default(),
+    });
+    json!({ "errors": [error] })
+}
+
+/// An error without a JSON body, coded from its status's reason phrase, so a
+/// `405` becomes `method_not_allowed`.
+fn plain_failure(body: &[u8], status: StatusCode) -> Value {
+    let reason = status.canonical_reason().unwrap_or("error");
+    let detail = String::from_utf8_lossy(body).trim().to_owned();
+    let error = json!({
+        "status": status.as_str(),
+        "code": reason.to_lowercase().replace(' ', "_"),
+        "detail": if detail.is_empty() { reason.to_lowercase() } else { detail },
+    });
+    json!({ "errors": [error] })
+}
@@ -0,0 +1,195 @@
+use std::fmt;
+
+#[cfg(feature = "server")]
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use sea_orm::{DbErr, RuntimeErr, SqlErr, SqlxError};
+#[cfg(feature = "server")]
+use serde::Serialize;
+use tracing::{error, info};
+
+/// Errors returned by the adapter and the route handlers.
+#[derive(Debug)]
+pub enum AppError {
+    /// No record matched the request.
+    NotFound,
+    /// No route matches the request path.
+    NoRoute,
+    /// The record existed but has expired.
+    Gone,
+    /// The write would violate a uniqueness constraint.
+    Conflict(String),
+    /// The provider account is already linked to a different user.
+    AccountAlreadyLinked,
+    /// The database returned an unexpected error.
+    Database(DbErr),
+    /// The request body could not be parsed.
+    MalformedBody(String),
+    /// The query string is missing a parameter or has one of the wrong type.
+    InvalidQuery(String),
+    /// The request body is not JSON.
+    UnsupportedMediaType,
+    /// The request body is larger than the configured limit.
+    PayloadTooLarge,
+    /// The request was well-formed but its contents were rejected.
+    Validation(String),
+    /// A dependency such as the database cannot be reached.
+    Unavailable(String),
+    /// The request did not carry a valid adapter secret.
+    Unauthorized,
+    /// The client has exceeded its request quota.
+    RateLimited,
+    /// The request took longer than the configured timeout.
+    Timeout,
+}
+
+/// JSON body sent to clients when a request fails.
+#[cfg(feature = "server")]
+#[derive(Serialize)]
+struct ErrorBody {
+    error: String,
+    code: &'static str,
+}
+
+impl AppError {
+    #[cfg(feature = "server")]
+    pub(crate) fn status(&self) -> StatusCode {
+        match self {
+            AppError::NotFound | AppError::NoRoute => StatusCode::NOT_FOUND,
+            AppError::Gone => StatusCode::GONE,
+            AppError::Conflict(_) | AppError::AccountAlreadyLinked => StatusCode::CONFLICT,
+            AppError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::MalformedBody(_) | AppError::InvalidQuery(_) => StatusCode::BAD_REQUEST,
+            AppError::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            AppError::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            AppError::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::Unauthorized => StatusCode::UNAUTHORIZED,
+            AppError::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+            AppError::Timeout => StatusCode::GATEWAY_TIMEOUT,
+        }
+    }
+
+    /// The client-facing description of the error.
+    pub(crate) fn message(&self) -> String {
+        match self {
+            AppError::NotFound => "record not found".to_owned(),
+            AppError::NoRoute => "no route for this path".to_owned(),
+            AppError::Gone => "record has expired".to_owned(),
+            AppError::AccountAlreadyLinked => {
+                "this account is already linked to another user".to_owned()
+            }
+            AppError::Unauthorized => "missing or invalid api key".to_owned(),
+            AppError::RateLimited => "too many requests".to_owned(),
+            AppError::Timeout => "request timed out".to_owned(),
+            AppError::UnsupportedMediaType => {
+                "expected an `application/json` request body".to_owned()
+            }
+            AppError::PayloadTooLarge => "request body is too large".to_owned(),
+            AppError::Conflict(msg)
+            | AppError::MalformedBody(msg)
+            | AppError::InvalidQuery(msg)
+            | AppError::Validation(msg)
+            | AppError::Unavailable(msg) => msg.to_owned(),
+            // don't leak driver details to the client
+            AppError::Database(_) => "internal database error".to_owned(),
+        }
+    }
+
+    #[cfg(feature = "server")]
+    fn code(&self) -> &'static str {
+        match self {
+            AppError::NotFound => "not_found",
+            AppError::NoRoute => "route_not_found",
+            AppError::Gone => "gone",
+            AppError::Conflict(_) => "conflict",
+            AppError::AccountAlreadyLinked => "account_already_linked",
+            AppError::Database(_) => "database_error",
+            AppError::MalformedBody(_) => "malformed_body",
+            AppError::InvalidQuery(_) => "invalid_query",
+            AppError::UnsupportedMediaType => "unsupported_media_type",
+            AppError::PayloadTooLarge => "payload_too_large",
+            AppError::Validation(_) => "validation_error",
+            AppError::Unavailable(_) => "service_unavailable",
+            AppError::Unauthorized => "unauthorized",
+            AppError::RateLimited => "rate_limited",
+            AppError::Timeout => "timeout",
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::Database(e) => write!(f, "database error: {e}"),
+            _ => f.write_str(&self.message()),
+        }
+    }
+}
+
+impl std::error::Error for AppError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AppError::Database(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<DbErr> for AppError {
+    fn from(e: DbErr) -> Self {
+        if is_disconnect(&e) {
+            error!("{e}");
+            return AppError::Unavailable("database unavailable".to_owned());
+        }
+        if let DbErr::RecordNotFound(_) | DbErr::RecordNotUpdated = e {
+            return AppError::NotFound;
+        }
+        match e.sql_err() {
+            Some(SqlErr::UniqueConstraintViolation(msg)) => {
+                // the driver's message names tables and indexes, so it stays in the logs
+                info!("unique constraint violated: {msg}");
+                AppError::Conflict("resource already exists".to_owned())
+            }
+            _ => AppError::Database(e),
+        }
+    }
+}
+
+/// Whether `e` means the connection to the database was lost or couldn't be
+/// made, as when Postgres restarts, rather than that the query itself failed.
+pub(crate) fn is_disconnect(e: &DbErr) -> bool {
+    let sqlx_err = match e {
+        DbErr::ConnectionAcquire(_) | DbErr::Conn(_) => return true,
+        DbErr::Query(RuntimeErr::SqlxError(e)) | DbErr::Exec(RuntimeErr::SqlxError(e)) => e,
+        _ => return false,
+    };
+    match sqlx_err {
+        SqlxError::Io(_)
+        | SqlxError::PoolTimedOut
+        | SqlxError::PoolClosed
+        | SqlxError::WorkerCrashed => true,
+        // Postgres' admin_shutdown, crash_shutdown and cannot_connect_now
+        SqlxError::Database(e) => e
+            .code()
+            .is_some_and(|code| matches!(code.as_ref(), "57P01" | "57P02" | "57P03")),
+        _ => false,
+    }
+}
+
+#[cfg(feature = "server")]
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        if let AppError::Database(e) = &self {
+            error!("{e}");
+        }
+        let body = ErrorBody {
+            error: self.message(),
+            code: self.code(),
+        };
+        (self.status(), Json(body)).into_response()
+    }
+}
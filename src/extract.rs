@@ -0,0 +1,88 @@
+use axum::{
+    async_trait,
+    body::Bytes,
+    extract::{FromRequest, FromRequestParts, Query},
+    http::{header::CONTENT_TYPE, request::Parts, HeaderMap, Request},
+    response::{IntoResponse, Response},
+};
+use serde::de::DeserializeOwned;
+use serde_json::error::Category;
+
+use crate::error::AppError;
+
+/// Like [`axum::Json`], but rejects bodies with a structured [`AppError`].
+///
+/// Syntax errors are a `400` naming the byte offset where parsing failed. Bodies
+/// that parse but don't match the expected shape, such as an unknown field, are a `422`.
+pub struct AppJson<T>(pub T);
+
+#[async_trait]
+impl<T, S, B> FromRequest<S, B> for AppJson<T>
+where
+    T: DeserializeOwned,
+    Bytes: FromRequest<S, B>,
+    S: Send + Sync,
+    B: Send + 'static,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
+        if !is_json(req.headers()) {
+            return Err(AppError::UnsupportedMediaType.into_response());
+        }
+        let bytes = Bytes::from_request(req, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        serde_json::from_slice(&bytes).map(AppJson).map_err(|e| {
+            let error = match e.classify() {
+                Category::Data => AppError::Validation(e.to_string()),
+                Category::Syntax | Category::Eof | Category::Io => {
+                    AppError::MalformedBody(format!(
+                        "invalid JSON at byte {}: {e}",
+                        byte_offset(&bytes, e.line(), e.column())
+                    ))
+                }
+            };
+            error.into_response()
+        })
+    }
+}
+
+/// Like [`axum::extract::Query`], but a missing or mistyped parameter is a
+/// `400` with the usual JSON error body.
+pub struct AppQuery<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequestParts<S> for AppQuery<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        Query::from_request_parts(parts, state)
+            .await
+            .map(|Query(value)| AppQuery(value))
+            .map_err(|e| AppError::InvalidQuery(e.body_text()).into_response())
+    }
+}
+
+/// `application/json` or any `+json` media type.
+fn is_json(headers: &HeaderMap) -> bool {
+    let Some(content_type) = headers.get(CONTENT_TYPE).and_then(|v| v.to_str().ok()) else {
+        return false;
+    };
+    let essence = content_type.split(';').next().unwrap_or_default().trim();
+    essence.eq_ignore_ascii_case("application/json") || essence.ends_with("+json")
+}
+
+/// Converts serde_json's one-based line and column into a byte offset.
+fn byte_offset(bytes: &[u8], line: usize, column: usize) -> usize {
+    let line_start: usize = bytes
+        .split(|&b| b == b'\n')
+        .take(line.saturating_sub(1))
+        .map(|line| line.len() + 1)
+        .sum();
+    line_start + column.saturating_sub(1)
+}
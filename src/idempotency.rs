@@ -0,0 +1,216 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    body::{Body, Bytes, Full, HttpBody},
+    extract::State,
+    http::{header::CONTENT_TYPE, HeaderValue, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use tracing::error;
+
+use crate::{
+    client_ip::{client_ip, TrustedProxies},
+    error::AppError,
+};
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+/// Forget expired keys once this many responses are being stored.
+const MAX_STORED_RESPONSES: usize = 10_000;
+
+/// A response kept so a retried request can be answered without running it again.
+struct StoredResponse {
+    status: StatusCode,
+    content_type: Option<HeaderValue>,
+    body: Bytes,
+}
+
+impl StoredResponse {
+    fn to_response(&self) -> Response {
+        let mut response = (self.status, Full::from(self.body.clone())).into_response();
+        if let Some(content_type) = &self.content_type {
+            response
+                .headers_mut()
+                .insert(CONTENT_TYPE, content_type.clone());
+        }
+        response
+    }
+}
+
+/// A key that has been seen, along with the body of the request that first
+/// used it. The response is `None` while that request is still running.
+struct Entry {
+    stored_at: Instant,
+    request_body: Bytes,
+    response: Option<StoredResponse>,
+}
+
+/// Responses to requests that carried an `Idempotency-Key`, kept in memory for `ttl`.
+#[derive(Clone)]
+pub struct IdempotencyCache {
+    entries: Arc<Mutex<HashMap<String, Entry>>>,
+    ttl: Duration,
+    max_body_bytes: usize,
+    trusted_proxies: Arc<TrustedProxies>,
+}
+
+impl IdempotencyCache {
+    /// Keys are scoped to the client address, found the same way as for rate
+    /// limiting. Request bodies over `max_body_bytes` are refused.
+    pub fn new(ttl: Duration, max_body_bytes: usize, trusted_proxies: Arc<TrustedProxies>) -> Self {
+        Self {
+            entries: Arc::default(),
+            ttl,
+            max_body_bytes,
+            trusted_proxies,
+        }
+    }
+
+    /// Replays the stored response for `key`, or reserves the key for this
+    /// request when it hasn't been seen.
+    fn check(&self, key: &str, request_body: &Bytes) -> Result<Option<Response>, AppError> {
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(entry) = entries
+            .get(key)
+            .filter(|entry| entry.stored_at.elapsed() < self.ttl)
+        {
+            if entry.request_body != *request_body {
+                return Err(AppError::Validation(
+                    "Idempotency-Key was already used with a different request body".to_owned(),
+                ));
+            }
+            return match &entry.response {
+                Some(stored) => Ok(Some(stored.to_response())),
+                None => Err(AppError::Conflict(
+                    "a request with this Idempotency-Key is still in progress".to_owned(),
+                )),
+            };
+        }
+        if entries.len() >= MAX_STORED_RESPONSES {
+            entries.retain(|_, entry| entry.stored_at.elapsed() < self.ttl);
+        }
+        entries.insert(
+            key.to_owned(),
+            Entry {
+                stored_at: Instant::now(),
+                request_body: request_body.clone(),
+                response: None,
+            },
+        );
+        Ok(None)
+    }
+}
+
+/// Releases a reserved key unless a response was stored for it, so a request
+/// that failed or was cancelled can be retried under the same key.
+struct Reservation {
+    cache: IdempotencyCache,
+    key: String,
+    completed: bool,
+}
+
+impl Reservation {
+    fn complete(mut self, stored: StoredResponse) {
+        if let Some(entry) = self.cache.entries.lock().unwrap().get_mut(&self.key) {
+            entry.response = Some(stored);
+        }
+        self.completed = true;
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        if !self.completed {
+            if let Ok(mut entries) = self.cache.entries.lock() {
+                entries.remove(&self.key);
+            }
+        }
+    }
+}
+
+/// Replays the stored response when a client repeats an `Idempotency-Key`
+/// with the same body. A different body is rejected with a `422`, and a
+/// repeat that arrives while the first request is still running gets a `409`.
+///
+/// Server errors are not stored, so the client can retry them.
+pub async fn replay(
+    State(cache): State<IdempotencyCache>,
+    request: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    let Some(key) = request
+        .headers()
+        .get(IDEMPOTENCY_KEY_HEADER)
+        .and_then(|value| value.to_str().ok())
+    else {
+        return next.run(request).await;
+    };
+    let caller = client_ip(&request, &cache.trusted_proxies)
+        .map(|ip| ip.to_string())
+        .unwrap_or_default();
+    let key = format!(
+        "{caller} {} {}:{key}",
+        request.method(),
+        request.uri().path()
+    );
+
+    let (parts, body) = request.into_parts();
+    let request_body = match buffer_body(body, cache.max_body_bytes).await {
+        Ok(bytes) => bytes,
+        Err(e) => return e.into_response(),
+    };
+    match cache.check(&key, &request_body) {
+        Ok(Some(response)) => return response,
+        Ok(None) => {}
+        Err(e) => return e.into_response(),
+    }
+    let reservation = Reservation {
+        cache: cache.clone(),
+        key,
+        completed: false,
+    };
+
+    let request = Request::from_parts(parts, Body::from(request_body));
+    let response = next.run(request).await;
+    if response.status().is_server_error() {
+        return response;
+    }
+    let (parts, body) = response.into_parts();
+    let body = match hyper::body::to_bytes(body).await {
+        Ok(body) => body,
+        Err(e) => {
+            error!("failed to buffer response for idempotency key: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let stored = StoredResponse {
+        status: parts.status,
+        content_type: parts.headers.get(CONTENT_TYPE).cloned(),
+        body,
+    };
+    let response = stored.to_response();
+    reservation.complete(stored);
+    response
+}
+
+/// Reads the whole request body so it can be compared with the one stored for
+/// the key, giving up once it passes `limit` bytes.
+async fn buffer_body(mut body: Body, limit: usize) -> Result<Bytes, AppError> {
+    let mut buffered = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|e| {
+            error!("failed to read request body: {e}");
+            AppError::MalformedBody("failed to read the request body".to_owned())
+        })?;
+        if buffered.len() + chunk.len() > limit {
+            return Err(AppError::PayloadTooLarge);
+        }
+        buffered.extend_from_slice(&chunk);
+    }
+    Ok(Bytes::from(buffered))
+}
@@ -0,0 +1,45 @@
+//! The auth adapter's HTTP API, built on the `entities` models.
+//!
+//! With the default `server` feature off, only [`AuthAdapter`], the entities
+//! and the session cleanup task are compiled, without axum or the tower stack.
+
+pub mod adapter;
+#[cfg(feature = "server")]
+mod auth;
+pub mod cleanup;
+#[cfg(feature = "server")]
+pub mod client_ip;
+#[cfg(feature = "server")]
+pub mod config;
+#[cfg(feature = "server")]
+pub mod envelope;
+mod error;
+#[cfg(feature = "server")]
+mod extract;
+#[cfg(feature = "server")]
+pub mod idempotency;
+#[cfg(feature = "server")]
+pub mod maintenance;
+#[cfg(feature = "server")]
+mod rate_limit;
+#[cfg(feature = "server")]
+mod routes;
+#[cfg(feature = "server")]
+mod security_headers;
+#[cfg(feature = "server")]
+mod server;
+#[cfg(feature = "server")]
+pub mod shutdown;
+#[cfg(feature = "server")]
+pub mod telemetry;
+pub mod token_encryption;
+#[cfg(feature = "server")]
+pub mod tx;
+#[cfg(all(unix, feature = "server"))]
+pub mod uds;
+
+pub use adapter::AuthAdapter;
+pub use entities;
+pub use error::AppError;
+#[cfg(feature = "server")]
+pub use server::app;
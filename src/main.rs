@@ -1,55 +1,266 @@
-mod routes;
-
-use axum::{
-    routing::{get, post},
-    Router,
+use anyhow::Context;
+#[cfg(unix)]
+use auth_adapter::uds;
+use auth_adapter::{
+    app, cleanup,
+    config::env_or,
+    maintenance::{self, Maintenance},
+    shutdown, telemetry,
+};
+use axum::{middleware, routing::get, Router};
+use axum_server::{tls_rustls::RustlsConfig, Handle};
+use email_address::EmailAddress;
+use entities::user;
+use migration::{Migrator, MigratorTrait};
+use sea_orm::{
+    ActiveModelTrait, ConnectOptions, Database, DatabaseConnection, DbErr, EntityTrait, Set,
+};
+use std::{
+    future::{ready, Future},
+    net::SocketAddr,
+    pin::Pin,
+    sync::Arc,
+    time::Duration,
 };
-use sea_orm::Database;
-use std::{net::SocketAddr, sync::Arc};
 use tokio::signal;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// Where `/metrics` is served when `METRICS_ADDR` is unset: loopback only, so
+/// a scraper on another host needs the address set explicitly.
+const DEFAULT_METRICS_ADDR: &str = "127.0.0.1:9091";
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     dotenvy::dotenv().ok();
+    telemetry::init_tracing()?;
+
     let db_url = std::env::var("DATABASE_URL").expect("missing db url in env");
-    let conn = Database::connect(&db_url).await?;
+    let mut options = ConnectOptions::new(db_url);
+    options
+        .max_connections(env_or("DB_MAX_CONNECTIONS", 10))
+        .min_connections(env_or("DB_MIN_CONNECTIONS", 1))
+        .connect_timeout(Duration::from_secs(env_or("DB_CONNECT_TIMEOUT_SECS", 8)))
+        .idle_timeout(Duration::from_secs(env_or("DB_IDLE_TIMEOUT_SECS", 600)))
+        .sqlx_logging(env_or("DB_SQLX_LOGGING", false));
+    let conn = connect_with_retry(
+        options,
+        env_or("DB_CONNECT_MAX_ATTEMPTS", 5),
+        Duration::from_millis(env_or("DB_CONNECT_RETRY_BASE_MS", 500)),
+    )
+    .await?;
+    if std::env::args().any(|arg| arg == "--migrate") || env_or("RUN_MIGRATIONS_ON_START", false) {
+        Migrator::up(&conn, None).await?;
+        info!("database migrations applied");
+    }
+    if let Ok(email) = std::env::var("SEED_ADMIN_EMAIL") {
+        seed_admin(&conn, &email).await?;
+    }
     let adapter = Arc::new(conn);
 
-    let app = Router::new()
-        .route("/health", get(routes::health))
-        .route(
-            "/users",
-            post(routes::create_user)
-                .get(routes::get_users)
-                .delete(routes::delete_user)
-                .put(routes::update_user),
-        )
-        .route(
-            "/accounts",
-            post(routes::create_account).delete(routes::delete_account),
-        )
-        .route(
-            "/session",
-            post(routes::create_session)
-                .put(routes::update_session)
-                .delete(routes::delete_session),
-        )
-        .route(
-            "/verification-token",
-            post(routes::create_verif_token).delete(routes::delete_verif_token),
+    let shutdown_token = CancellationToken::new();
+    let cleanup_interval = Duration::from_secs(env_or("SESSION_CLEANUP_INTERVAL_SECS", 300));
+    let cleanup = tokio::spawn(cleanup::prune_expired_sessions(
+        Arc::clone(&adapter),
+        cleanup_interval,
+        shutdown_token.child_token(),
+    ));
+
+    // kept off the API listener, so scrapes need neither the API key nor a rate limit
+    let metrics_addr = std::env::var("METRICS_ADDR")
+        .ok()
+        .filter(|addr| !addr.is_empty())
+        .unwrap_or_else(|| DEFAULT_METRICS_ADDR.to_owned());
+    let metrics_addr: SocketAddr = metrics_addr
+        .parse()
+        .with_context(|| format!("METRICS_ADDR is not a socket address: {metrics_addr}"))?;
+    let metrics = telemetry::install_recorder();
+    let metrics_server = axum::Server::try_bind(&metrics_addr)?
+        .serve(
+            Router::new()
+                .route("/metrics", get(move || ready(metrics.render())))
+                .into_make_service(),
         )
-        .route("/session-user", get(routes::get_session_and_user))
-        .with_state(adapter);
-
-    let addr = SocketAddr::from(([0, 0, 0, 0], 4000));
-    println!("listening on {}", addr);
-    axum::Server::bind(&addr)
-        .serve(app.into_make_service())
-        .with_graceful_shutdown(shutdown_signal())
-        .await?;
+        .with_graceful_shutdown({
+            let shutdown_token = shutdown_token.clone();
+            async move { shutdown_token.cancelled().await }
+        });
+    info!("serving metrics on {}", metrics_addr);
+    tokio::spawn(async move {
+        if let Err(e) = metrics_server.await {
+            warn!("metrics server failed: {e}");
+        }
+    });
+
+    let in_flight = shutdown::InFlight::default();
+    let maintenance = Maintenance::from_env();
+    #[cfg(unix)]
+    tokio::spawn(reload_maintenance_on_sighup(maintenance.clone()));
+    let app = app(adapter)
+        .layer(middleware::from_fn_with_state(
+            in_flight.clone(),
+            shutdown::track_in_flight,
+        ))
+        .layer(middleware::from_fn_with_state(
+            maintenance,
+            maintenance::reject_during_maintenance,
+        ));
+
+    let shutdown = {
+        let shutdown_token = shutdown_token.clone();
+        async move {
+            shutdown_signal().await;
+            shutdown_token.cancel();
+        }
+    };
+    let tls_paths = std::env::var("TLS_CERT_PATH")
+        .ok()
+        .zip(std::env::var("TLS_KEY_PATH").ok());
+    let server: Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send>> =
+        match std::env::var("BIND_UDS") {
+            #[cfg(unix)]
+            Ok(path) => {
+                let accept = uds::UdsAccept::bind(&path)?;
+                info!("listening on unix socket {}", path);
+                let server = axum::Server::builder(accept)
+                    .serve(app.into_make_service())
+                    .with_graceful_shutdown(shutdown);
+                Box::pin(async move { Ok(server.await?) })
+            }
+            _ => {
+                let addr = SocketAddr::from(([0, 0, 0, 0], 4000));
+                let service = app.into_make_service_with_connect_info::<SocketAddr>();
+                if let Some((cert_path, key_path)) = tls_paths {
+                    let tls = RustlsConfig::from_pem_file(&cert_path, &key_path).await?;
+                    #[cfg(unix)]
+                    tokio::spawn(reload_tls_on_sighup(tls.clone(), cert_path, key_path));
+                    let handle = Handle::new();
+                    tokio::spawn({
+                        let handle = handle.clone();
+                        async move {
+                            shutdown.await;
+                            handle.graceful_shutdown(None);
+                        }
+                    });
+                    info!("listening on {} with TLS", addr);
+                    let server = axum_server::bind_rustls(addr, tls)
+                        .handle(handle)
+                        .serve(service);
+                    Box::pin(async move { Ok(server.await?) })
+                } else {
+                    info!("listening on {}", addr);
+                    let server = axum::Server::bind(&addr)
+                        .serve(service)
+                        .with_graceful_shutdown(shutdown);
+                    Box::pin(async move { Ok(server.await?) })
+                }
+            }
+        };
+
+    let shutdown_timeout = Duration::from_secs(env_or("SHUTDOWN_TIMEOUT_SECS", 30));
+    let drain_deadline = async {
+        shutdown_token.cancelled().await;
+        tokio::time::sleep(shutdown_timeout).await;
+    };
+    tokio::select! {
+        result = server => result?,
+        _ = drain_deadline => {
+            warn!(
+                "shutdown timed out after {shutdown_timeout:?} with {} requests still in flight",
+                in_flight.count()
+            );
+        }
+    }
+    // an in-progress prune finishes before the process exits
+    cleanup.await?;
+    opentelemetry::global::shutdown_tracer_provider();
+    Ok(())
+}
+
+/// Creates a user for `email` when the database has no users yet. Later boots
+/// find the existing users and leave them alone.
+async fn seed_admin(conn: &DatabaseConnection, email: &str) -> anyhow::Result<()> {
+    let email = email.trim().to_lowercase();
+    anyhow::ensure!(
+        EmailAddress::is_valid(&email),
+        "SEED_ADMIN_EMAIL is not a valid email address: {email}"
+    );
+    if user::Entity::find().one(conn).await?.is_some() {
+        info!("users already exist, not seeding an admin");
+        return Ok(());
+    }
+    let admin = user::ActiveModel {
+        id: Set(Uuid::new_v4().to_string()),
+        email: Set(Some(email)),
+        ..Default::default()
+    }
+    .insert(conn)
+    .await?;
+    info!(
+        "seeded admin user {} ({})",
+        admin.id,
+        admin.email.unwrap_or_default()
+    );
     Ok(())
 }
 
+/// Connects to the database, doubling the delay after each failed attempt.
+async fn connect_with_retry(
+    options: ConnectOptions,
+    max_attempts: u32,
+    base_delay: Duration,
+) -> Result<DatabaseConnection, DbErr> {
+    let max_attempts = max_attempts.max(1);
+    let mut attempt = 1;
+    loop {
+        match Database::connect(options.clone()).await {
+            Ok(conn) => return Ok(conn),
+            Err(e) if attempt < max_attempts => {
+                let delay = base_delay.saturating_mul(2u32.saturating_pow(attempt - 1));
+                warn!("database connection attempt {attempt}/{max_attempts} failed: {e}, retrying in {delay:?}");
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Re-reads the certificate and key whenever the process receives SIGHUP.
+#[cfg(unix)]
+async fn reload_tls_on_sighup(tls: RustlsConfig, cert_path: String, key_path: String) {
+    let mut hangup = signal::unix::signal(signal::unix::SignalKind::hangup())
+        .expect("failed to install signal handler");
+    while hangup.recv().await.is_some() {
+        match tls.reload_from_pem_file(&cert_path, &key_path).await {
+            Ok(()) => info!("reloaded TLS certificate from {cert_path}"),
+            Err(e) => warn!("failed to reload TLS certificate, keeping the old one: {e}"),
+        }
+    }
+}
+
+/// Re-reads `MAINTENANCE_MODE` whenever the process receives SIGHUP. The
+/// `.env` file is checked first, since the process environment can't be
+/// changed from outside.
+#[cfg(unix)]
+async fn reload_maintenance_on_sighup(maintenance: Maintenance) {
+    let mut hangup = signal::unix::signal(signal::unix::SignalKind::hangup())
+        .expect("failed to install signal handler");
+    while hangup.recv().await.is_some() {
+        let from_file = dotenvy::dotenv_iter().ok().and_then(|vars| {
+            vars.filter_map(Result::ok)
+                .find(|(key, _)| key == "MAINTENANCE_MODE")
+                .map(|(_, value)| value)
+        });
+        let value = from_file.or_else(|| std::env::var("MAINTENANCE_MODE").ok());
+        match value.unwrap_or_default().parse() {
+            Ok(mode) => maintenance.set(mode),
+            Err(e) => warn!("keeping the current maintenance mode: {e}"),
+        }
+    }
+}
+
 async fn shutdown_signal() {
     let ctrl_c = async {
         signal::ctrl_c()
@@ -73,5 +284,5 @@ async fn shutdown_signal() {
         _ = terminate => {},
     }
 
-    println!("signal received, starting graceful shutdown");
+    info!("signal received, starting graceful shutdown");
 }
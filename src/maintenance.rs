@@ -0,0 +1,113 @@
+use std::{
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        Arc,
+    },
+};
+
+use axum::{
+    extract::State,
+    http::{header::RETRY_AFTER, HeaderValue, Method, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use tracing::info;
+
+use crate::{config::env_or, error::AppError};
+
+/// How much of the API is turned away while maintenance is under way.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Mode {
+    Off,
+    /// Reads are served, anything that writes gets a 503.
+    ReadOnly,
+    /// Every request except the health checks gets a 503.
+    On,
+}
+
+impl FromStr for Mode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "" | "false" | "off" => Ok(Mode::Off),
+            "read-only" | "readonly" => Ok(Mode::ReadOnly),
+            "true" | "on" => Ok(Mode::On),
+            other => Err(format!("unknown maintenance mode {other}")),
+        }
+    }
+}
+
+/// Attached to requests let through in [`Mode::ReadOnly`], so handlers whose
+/// reads would also clean up, such as deleting an expired session, skip the write.
+#[derive(Clone, Copy, Debug)]
+pub struct ReadOnlyRequest;
+
+/// The current maintenance mode, shared with whatever toggles it at runtime.
+#[derive(Clone)]
+pub struct Maintenance {
+    mode: Arc<AtomicU8>,
+    retry_after: HeaderValue,
+}
+
+impl Maintenance {
+    /// Starts in `MAINTENANCE_MODE`, telling rejected clients to come back
+    /// after `MAINTENANCE_RETRY_AFTER_SECS`.
+    pub fn from_env() -> Self {
+        let maintenance = Maintenance {
+            mode: Arc::new(AtomicU8::new(Mode::Off as u8)),
+            retry_after: HeaderValue::from(env_or("MAINTENANCE_RETRY_AFTER_SECS", 60u64)),
+        };
+        maintenance.set(env_or("MAINTENANCE_MODE", Mode::Off));
+        maintenance
+    }
+
+    pub fn mode(&self) -> Mode {
+        match self.mode.load(Ordering::Relaxed) {
+            1 => Mode::ReadOnly,
+            2 => Mode::On,
+            _ => Mode::Off,
+        }
+    }
+
+    pub fn set(&self, mode: Mode) {
+        if self.mode.swap(mode as u8, Ordering::Relaxed) != mode as u8 {
+            info!("maintenance mode is now {mode:?}");
+        }
+    }
+}
+
+/// Answers 503 with `Retry-After` while maintenance is on. Health checks are
+/// always let through so the process isn't restarted mid-migration.
+pub async fn reject_during_maintenance<B>(
+    State(maintenance): State<Maintenance>,
+    mut request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let path = request.uri().path();
+    let is_health = path == "/health" || path.starts_with("/health/");
+    let is_read = matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    );
+    let mode = maintenance.mode();
+    let rejected = match mode {
+        Mode::Off => false,
+        Mode::ReadOnly => !is_health && !is_read,
+        Mode::On => !is_health,
+    };
+    if !rejected {
+        if mode == Mode::ReadOnly {
+            request.extensions_mut().insert(ReadOnlyRequest);
+        }
+        return next.run(request).await;
+    }
+    let mut response =
+        AppError::Unavailable("the adapter is down for maintenance".to_owned()).into_response();
+    response
+        .headers_mut()
+        .insert(RETRY_AFTER, maintenance.retry_after);
+    response
+}
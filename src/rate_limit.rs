@@ -0,0 +1,66 @@
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    num::NonZeroU32,
+    sync::Arc,
+};
+
+use axum::{
+    extract::State,
+    http::{Method, Request},
+    middleware::Next,
+    response::Response,
+};
+use governor::{DefaultKeyedRateLimiter, Quota, RateLimiter};
+
+use crate::{
+    client_ip::{client_ip, TrustedProxies},
+    error::AppError,
+};
+
+/// Forget idle clients once this many are being tracked.
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+#[derive(Clone)]
+pub struct Limiter {
+    limiter: Arc<DefaultKeyedRateLimiter<IpAddr>>,
+    /// Proxies whose `X-Forwarded-For` entries are believed.
+    trusted_proxies: Arc<TrustedProxies>,
+}
+
+pub fn limiter(per_second: u32, burst: u32, trusted_proxies: Arc<TrustedProxies>) -> Limiter {
+    let per_second = NonZeroU32::new(per_second).unwrap_or(NonZeroU32::MIN);
+    let burst = NonZeroU32::new(burst).unwrap_or(per_second);
+    Limiter {
+        limiter: Arc::new(RateLimiter::keyed(
+            Quota::per_second(per_second).allow_burst(burst),
+        )),
+        trusted_proxies,
+    }
+}
+
+/// Throttles create/update/delete requests per client IP. Reads are not limited.
+/// Requests whose client address is unknown share one limit.
+pub async fn limit_mutations<B>(
+    State(Limiter {
+        limiter,
+        trusted_proxies,
+    }): State<Limiter>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Result<Response, AppError> {
+    if matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    ) {
+        return Ok(next.run(request).await);
+    }
+
+    let ip = client_ip(&request, &trusted_proxies).unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+    if limiter.len() > MAX_TRACKED_CLIENTS {
+        limiter.retain_recent();
+    }
+    if limiter.check_key(&ip).is_err() {
+        return Err(AppError::RateLimited);
+    }
+    Ok(next.run(request).await)
+}
@@ -1,408 +1,759 @@
-use std::{collections::HashMap, sync::Arc};
+//! Route handlers.
+//!
+//! Lookups that find nothing respond `200 OK` with a JSON `null` body, which
+//! NextAuth's adapter methods such as `getUserByEmail` and `getSessionAndUser`
+//! expect. Updates and deletes that target a missing record return `404`.
+
+use std::sync::Arc;
 
 use axum::{
     debug_handler,
-    extract::{Query, State},
-    http::StatusCode,
-    response::IntoResponse,
-    Form, Json,
-};
-use entities::{
-    account, session, session::Model as Session, user, user::Model as User, verification_token,
+    extract::{OriginalUri, Path, State},
+    http::{
+        header::{CACHE_CONTROL, ETAG, IF_NONE_MATCH, LINK},
+        HeaderMap, HeaderValue, StatusCode, Uri,
+    },
+    response::{IntoResponse, Response},
+    Extension, Json,
 };
+use chrono::Utc;
+use entities::{account, session::Model as Session, timestamp, user, verification_token};
 use sea_orm::{
-    ActiveModelTrait, ColumnTrait, Condition, DatabaseConnection, EntityTrait, ModelTrait,
-    QueryFilter, Set,
+    prelude::{DateTimeUtc, DateTimeWithTimeZone},
+    ConnectionTrait, DatabaseConnection, DbBackend,
 };
 use serde::{Deserialize, Serialize};
+use tracing::{debug, error};
+
+use crate::{
+    adapter::{
+        AccountTokenUpdate, AccountWithExpiry, AuthAdapter, NewUser, SessionLookup, SessionUpdate,
+        UserAndSession, UserFilter, UserUpdate, UserWithRelations,
+    },
+    config::Config,
+    error::AppError,
+    extract::{AppJson, AppQuery},
+    maintenance::ReadOnlyRequest,
+    tx::Tx,
+};
 
 /// Find a user in the database. If no query is provided, all users are returned.
 #[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct UserSearchQuery {
-    /// Search by user's `id`.
-    id: Option<String>,
     /// Search by user `email` address.
     email: Option<String>,
     /// Search by provider account `name`.
     provider: Option<String>,
     /// Search by provider account `id`.
+    #[serde(alias = "provider_account_id")]
     provider_account_id: Option<String>,
+    /// Maximum number of users to return when listing. Defaults to 50, capped at 500.
+    limit: Option<u64>,
+    /// Return users whose `id` sorts after this one (the previous page's `next_cursor`).
+    cursor: Option<String>,
+    /// When listing, only return users whose name or email contains this,
+    /// ignoring case.
+    q: Option<String>,
+    /// When listing, only return users whose email is (or isn't) verified.
+    #[serde(alias = "email_verified")]
+    email_verified: Option<bool>,
+    /// When listing, only return users created at or after this time.
+    #[serde(alias = "created_after")]
+    created_after: Option<DateTimeWithTimeZone>,
+    /// When listing, only return users created before this time.
+    #[serde(alias = "created_before")]
+    created_before: Option<DateTimeWithTimeZone>,
+}
+
+const DEFAULT_PAGE_SIZE: u64 = 50;
+const MAX_PAGE_SIZE: u64 = 500;
+
+fn missing_param(name: &str) -> AppError {
+    AppError::Validation(format!("missing `{name}` query parameter"))
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct CreateUserQuery {
+    /// Run the validation and duplicate checks without inserting anything.
+    #[serde(default)]
+    validate_only: bool,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum CreateUserResult {
+    Created(Box<user::Model>),
+    Validated { valid: bool },
 }
 
 #[debug_handler]
 pub async fn create_user(
-    State(state): State<Arc<DatabaseConnection>>,
-    Json(payload): Json<user::Model>,
-) -> Result<Json<user::Model>, StatusCode> {
-    let item: user::ActiveModel = payload.into();
-    match item.insert(&*state).await {
-        Ok(value) => Ok(Json(value)),
-        Err(e) => {
-            eprintln!("{e}");
-            Err(StatusCode::INTERNAL_SERVER_ERROR)
+    Extension(adapter): Extension<AuthAdapter>,
+    Extension(config): Extension<Arc<Config>>,
+    AppQuery(query): AppQuery<CreateUserQuery>,
+    AppJson(payload): AppJson<NewUser>,
+) -> Result<Json<CreateUserResult>, AppError> {
+    payload.validate()?;
+    let email = payload.email.clone();
+    if query.validate_only {
+        if let Some(email) = &email {
+            if adapter.get_user_by_email(email).await?.is_some() {
+                return Err(AppError::Validation(format!(
+                    "a user with email {} already exists",
+                    email.to_lowercase()
+                )));
+            }
+        }
+        return Ok(Json(CreateUserResult::Validated { valid: true }));
+    }
+
+    match adapter.create_user(payload).await {
+        Ok(user) => Ok(Json(CreateUserResult::Created(Box::new(user)))),
+        // a racing sign-in already created this user, so hand that one back
+        Err(AppError::Conflict(msg)) if config.create_user_idempotent => {
+            let existing = match email {
+                Some(email) => adapter.get_user_by_email(&email).await?,
+                None => None,
+            };
+            existing
+                .map(|user| Json(CreateUserResult::Created(Box::new(user))))
+                .ok_or(AppError::Conflict(msg))
         }
+        Err(e) => Err(e),
     }
 }
 
+#[derive(Debug, Default, Deserialize)]
+pub struct BulkUserQuery {
+    /// Roll back every insert if any record fails.
+    #[serde(default)]
+    atomic: bool,
+}
+
+/// Outcome for one record of a bulk request, in request order.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum BulkUserResult {
+    Created { id: String },
+    Failed { error: String },
+}
+
+impl From<AppError> for BulkUserResult {
+    fn from(e: AppError) -> Self {
+        if let AppError::Database(e) = &e {
+            error!("{e}");
+        }
+        BulkUserResult::Failed { error: e.message() }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct BulkUserReport {
+    /// `false` if an atomic batch was rolled back.
+    pub committed: bool,
+    pub results: Vec<BulkUserResult>,
+}
+
+/// Creates many users at once. Without `atomic=true`, failed records are
+/// reported and the rest are still inserted.
+///
+/// Batches larger than `MAX_BULK_SIZE` are rejected with a `422` before
+/// anything is written, so one request can't hold a long transaction.
+pub async fn create_users_bulk(
+    Extension(adapter): Extension<AuthAdapter>,
+    Extension(config): Extension<Arc<Config>>,
+    AppQuery(query): AppQuery<BulkUserQuery>,
+    AppJson(payloads): AppJson<Vec<NewUser>>,
+) -> Result<(StatusCode, Json<BulkUserReport>), AppError> {
+    if payloads.len() > config.max_bulk_size {
+        return Err(AppError::Validation(format!(
+            "at most {} users can be created per request, got {}",
+            config.max_bulk_size,
+            payloads.len()
+        )));
+    }
+    let created = adapter.create_users(payloads, query.atomic).await?;
+    // a rolled back batch is answered with the status of the failure that stopped it
+    let status = match created.results.last() {
+        Some(Err(e)) if !created.committed => e.status(),
+        _ => StatusCode::OK,
+    };
+    let results = created
+        .results
+        .into_iter()
+        .map(|result| match result {
+            Ok(user) => BulkUserResult::Created { id: user.id },
+            Err(e) => e.into(),
+        })
+        .collect();
+    let report = BulkUserReport {
+        committed: created.committed,
+        results,
+    };
+    Ok((status, Json(report)))
+}
+
+/// A page of users, ordered by `id`.
+#[derive(Serialize, Deserialize)]
+pub struct UserPage {
+    pub data: Vec<user::Model>,
+    /// Pass as `cursor` to fetch the next page. `None` on the last page.
+    pub next_cursor: Option<String>,
+}
+
 #[derive(Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum UserResult {
     Single(user::Model),
     Multiple(Vec<user::Model>),
+    Page(UserPage),
+}
+
+/// Looks up a user by id. A found user carries an `ETag` derived from
+/// `updated_at`, and a matching `If-None-Match` gets a bodiless `304`.
+#[debug_handler]
+pub async fn get_user(
+    Extension(adapter): Extension<AuthAdapter>,
+    Extension(config): Extension<Arc<Config>>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Result<Response, AppError> {
+    let Some(user) = adapter.get_user(&id).await? else {
+        return Ok(Json(None::<user::Model>).into_response());
+    };
+    let etag = format!("\"{}\"", user.updated_at.timestamp_micros());
+    let cache_control = format!("private, max-age={}", config.user_cache_max_age);
+    let cache_headers = [(ETAG, etag.clone()), (CACHE_CONTROL, cache_control)];
+    if etag_matches(&headers, &etag) {
+        return Ok((StatusCode::NOT_MODIFIED, cache_headers).into_response());
+    }
+    Ok((cache_headers, Json(Some(user))).into_response())
+}
+
+/// Looks up a user along with everything linked to them, for pages that
+/// would otherwise fetch the user, accounts and sessions separately.
+#[debug_handler]
+pub async fn get_user_full(
+    Extension(adapter): Extension<AuthAdapter>,
+    Path(id): Path<String>,
+) -> Result<Json<Option<UserWithRelations>>, AppError> {
+    Ok(Json(adapter.get_user_with_relations(&id).await?))
+}
+
+/// Whether `If-None-Match` lists `etag` or is `*`. Weak validators match too.
+fn etag_matches(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get_all(IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
 }
 
 #[debug_handler]
 pub async fn get_users(
-    State(state): State<Arc<DatabaseConnection>>,
-    Query(params): Query<UserSearchQuery>,
-) -> Result<Json<UserResult>, StatusCode> {
-    if let Some(id) = params.id {
-        match user::Entity::find_by_id(id).one(&*state).await {
-            Ok(users) => {
-                if let Some(user) = users {
-                    return Ok(Json(UserResult::Single(user)));
-                } else {
-                    return Err(StatusCode::NO_CONTENT);
-                }
-            }
-            Err(e) => {
-                eprintln!("{e}");
-                return Err(StatusCode::NO_CONTENT);
-            }
-        }
-    } else if let Some(email) = params.email {
-        match user::Entity::find()
-            .filter(user::Column::Email.eq(email))
-            .one(&*state)
-            .await
-        {
-            Ok(item) => {
-                if let Some(user) = item {
-                    return Ok(Json(UserResult::Single(user)));
-                } else {
-                    return Err(StatusCode::NO_CONTENT);
-                }
-            }
-            Err(e) => {
-                eprintln!("{e}");
-                return Err(StatusCode::NO_CONTENT);
-            }
-        }
-    } else if params.provider_account_id.is_some() && params.provider.is_some() {
-        let id = params.provider_account_id.unwrap();
-        let name = params.provider.unwrap();
-        match account::Entity::find()
-            .filter(
-                Condition::all()
-                    .add(account::Column::Id.eq(id))
-                    .add(account::Column::Provider.eq(name)),
+    Extension(adapter): Extension<AuthAdapter>,
+    OriginalUri(uri): OriginalUri,
+    AppQuery(params): AppQuery<UserSearchQuery>,
+) -> Result<(HeaderMap, Json<Option<UserResult>>), AppError> {
+    if let Some(email) = params.email {
+        let user = adapter.get_user_by_email(&email).await?;
+        return Ok((HeaderMap::new(), Json(user.map(UserResult::Single))));
+    }
+
+    if params.provider_account_id.is_some() || params.provider.is_some() {
+        let users = adapter
+            .get_users_by_account(
+                params.provider_account_id.as_deref(),
+                params.provider.as_deref(),
             )
-            .find_with_related(user::Entity)
-            .all(&*state)
-            .await
-        {
-            Ok(result) => {
-                if let Some((_, users)) = result.first() {
-                    return Ok(Json(UserResult::Multiple(users.to_owned())));
-                } else {
-                    return Err(StatusCode::NO_CONTENT);
-                }
-            }
-            Err(e) => {
-                eprintln!("{e}");
-                return Err(StatusCode::INTERNAL_SERVER_ERROR);
-            }
-        }
-    } else if let Some(id) = params.provider_account_id {
-        match account::Entity::find()
-            .filter(Condition::all().add(account::Column::Id.eq(id)))
-            .find_with_related(user::Entity)
-            .all(&*state)
-            .await
-        {
-            Ok(result) => {
-                if let Some((_, users)) = result.first() {
-                    return Ok(Json(UserResult::Multiple(users.to_owned())));
-                } else {
-                    return Err(StatusCode::NO_CONTENT);
-                }
-            }
-            Err(e) => {
-                eprintln!("{e}");
-                return Err(StatusCode::INTERNAL_SERVER_ERROR);
-            }
-        }
-    } else if let Some(name) = params.provider {
-        match account::Entity::find()
-            .filter(Condition::all().add(account::Column::Provider.eq(name)))
-            .find_with_related(user::Entity)
-            .all(&*state)
-            .await
-        {
-            Ok(result) => {
-                if let Some((_, users)) = result.first() {
-                    return Ok(Json(UserResult::Multiple(users.to_owned())));
-                } else {
-                    return Err(StatusCode::NO_CONTENT);
-                }
-            }
-            Err(e) => {
-                eprintln!("{e}");
-                return Err(StatusCode::INTERNAL_SERVER_ERROR);
-            }
-        }
+            .await?;
+        return Ok((HeaderMap::new(), Json(users.map(UserResult::Multiple))));
     }
-    match user::Entity::find().all(&*state).await {
-        Ok(users) => Ok(Json(UserResult::Multiple(users))),
-        Err(e) => {
-            eprintln!("{e}");
-            Err(StatusCode::NO_CONTENT)
+
+    let limit = params
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    let filter = UserFilter {
+        q: params.q,
+        email_verified: params.email_verified,
+        created_after: params.created_after,
+        created_before: params.created_before,
+    };
+    let total = adapter.count_users(&filter).await?;
+    let data = adapter
+        .list_users(&filter, params.cursor.as_deref(), limit)
+        .await?;
+    let next_cursor = if data.len() as u64 == limit {
+        data.last().map(|user| user.id.clone())
+    } else {
+        None
+    };
+
+    let mut links = Vec::new();
+    if let Some(next_cursor) = &next_cursor {
+        links.push(page_link(&uri, Some(next_cursor), "next"));
+    }
+    if let Some(cursor) = &params.cursor {
+        let prev_cursor = adapter.previous_page_cursor(&filter, cursor, limit).await?;
+        links.push(page_link(&uri, prev_cursor.as_deref(), "prev"));
+    }
+    let mut headers = HeaderMap::new();
+    headers.insert("x-total-count", HeaderValue::from(total));
+    if !links.is_empty() {
+        if let Ok(link) = HeaderValue::from_str(&links.join(", ")) {
+            headers.insert(LINK, link);
         }
     }
+    let page = UserPage { data, next_cursor };
+    Ok((headers, Json(Some(UserResult::Page(page)))))
 }
 
-pub async fn update_user(
-    State(state): State<Arc<DatabaseConnection>>,
-    Query(query): Query<HashMap<String, String>>,
-    Form(form): Form<User>,
-) -> impl IntoResponse {
-    println!("{query:#?}");
-    if let Some(id) = query.get("id") {
-        if let Ok(Some(user)) = user::Entity::find_by_id(id).one(&*state).await {
-            let mut user: user::ActiveModel = user.into();
-            if let Some(name) = form.name {
-                user.name = Set(Some(name));
-            }
-            if let Some(email) = form.email {
-                user.email = Set(Some(email));
-            }
-            if let Some(email_verified) = form.email_verified {
-                user.email_verified = Set(Some(email_verified));
-            }
-            if let Some(image) = form.image {
-                user.image = Set(Some(image));
-            }
-            if let Err(e) = user.update(&*state).await {
-                eprintln!("{e}");
-                StatusCode::INTERNAL_SERVER_ERROR
-            } else {
-                StatusCode::OK
-            }
-        } else {
-            StatusCode::NOT_FOUND
-        }
+/// An RFC 5988 link to the same listing starting after `cursor`, or to the
+/// first page when `cursor` is `None`.
+fn page_link(uri: &Uri, cursor: Option<&str>, rel: &str) -> String {
+    let mut query: Vec<String> = uri
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .filter(|pair| !pair.is_empty() && !pair.starts_with("cursor="))
+        .map(str::to_owned)
+        .collect();
+    if let Some(cursor) = cursor {
+        query.push(format!("cursor={}", percent_encode(cursor)));
+    }
+    if query.is_empty() {
+        format!("<{}>; rel=\"{rel}\"", uri.path())
     } else {
-        eprintln!("No parameters provided");
-        StatusCode::UNPROCESSABLE_ENTITY
+        format!("<{}?{}>; rel=\"{rel}\"", uri.path(), query.join("&"))
     }
 }
 
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+/// Selects one user by `id`.
+#[derive(Debug, Deserialize)]
+pub struct UserIdQuery {
+    id: String,
+}
+
+/// Updates the user identified by the `id` query parameter from a JSON [`UserUpdate`] body.
+pub async fn update_user(
+    Extension(adapter): Extension<AuthAdapter>,
+    AppQuery(query): AppQuery<UserIdQuery>,
+    AppJson(form): AppJson<UserUpdate>,
+) -> Result<Json<user::Model>, AppError> {
+    debug!(?query, "updating user");
+    Ok(Json(adapter.update_user(&query.id, form).await?))
+}
+
 pub async fn delete_user(
+    Extension(adapter): Extension<AuthAdapter>,
+    tx: Tx,
+    AppQuery(query): AppQuery<UserIdQuery>,
+) -> Result<Json<user::Model>, AppError> {
+    Ok(Json(adapter.delete_user(&query.id, &*tx).await?))
+}
+
+/// Answers requests for paths the adapter doesn't serve.
+pub async fn no_route() -> AppError {
+    AppError::NoRoute
+}
+
+/// Liveness probe: the process is up and serving. Never touches the database,
+/// so a database outage doesn't get the pod restarted.
+pub async fn health_live() -> &'static str {
+    "ok"
+}
+
+/// Readiness probe: the database is reachable, so requests can be served.
+pub async fn health_ready(
     State(state): State<Arc<DatabaseConnection>>,
-    Query(query): Query<HashMap<String, String>>,
-) -> impl IntoResponse {
-    if let Some(id) = query.get("id") {
-        if let Ok(Some(user)) = user::Entity::find_by_id(id).one(&*state).await {
-            if let Err(e) = user.delete(&*state).await {
-                eprintln!("{e}");
-                return StatusCode::INTERNAL_SERVER_ERROR;
-            }
-            StatusCode::OK
-        } else {
-            StatusCode::NOT_FOUND
-        }
-    } else {
-        eprintln!("No parameters provided");
-        StatusCode::UNPROCESSABLE_ENTITY
+) -> Result<&'static str, AppError> {
+    if let Err(e) = state.ping().await {
+        error!("health check failed: {e}");
+        return Err(AppError::Unavailable("database is unreachable".to_owned()));
     }
+    Ok("hello")
 }
 
-pub async fn health() -> &'static str {
-    "hello"
+/// Database connectivity and connection pool usage.
+#[derive(Debug, Serialize)]
+pub struct HealthDetails {
+    pub database_reachable: bool,
+    pub pool_size: u32,
+    pub active_connections: u32,
+    pub idle_connections: u32,
 }
 
-#[debug_handler]
-pub async fn create_account(
+/// Reports pool usage alongside the database ping, so exhaustion shows up before it causes errors.
+pub async fn health_detailed(
     State(state): State<Arc<DatabaseConnection>>,
-    Json(payload): Json<account::Model>,
-) -> impl IntoResponse {
-    let item: account::ActiveModel = payload.into();
-    if let Err(e) = item.insert(&*state).await {
-        eprintln!("{e}");
-        StatusCode::INTERNAL_SERVER_ERROR
+) -> (StatusCode, Json<HealthDetails>) {
+    let database_reachable = match state.ping().await {
+        Ok(()) => true,
+        Err(e) => {
+            error!("health check failed: {e}");
+            false
+        }
+    };
+    let (pool_size, idle) = match state.get_database_backend() {
+        DbBackend::Postgres => {
+            let pool = state.get_postgres_connection_pool();
+            (pool.size(), pool.num_idle())
+        }
+        DbBackend::MySql => {
+            let pool = state.get_mysql_connection_pool();
+            (pool.size(), pool.num_idle())
+        }
+        DbBackend::Sqlite => {
+            let pool = state.get_sqlite_connection_pool();
+            (pool.size(), pool.num_idle())
+        }
+    };
+    let idle_connections = u32::try_from(idle).unwrap_or(u32::MAX).min(pool_size);
+    let status = if database_reachable {
+        StatusCode::OK
     } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    let details = HealthDetails {
+        database_reachable,
+        pool_size,
+        active_connections: pool_size - idle_connections,
+        idle_connections,
+    };
+    (status, Json(details))
+}
+
+/// Links a provider account through [`AuthAdapter::link_account`].
+///
+/// Responds `201` for a new link and `200` when an existing link, which keeps
+/// its original `id`, was refreshed.
+#[debug_handler]
+pub async fn create_account(
+    Extension(adapter): Extension<AuthAdapter>,
+    AppJson(payload): AppJson<account::Model>,
+) -> Result<(StatusCode, Json<account::Model>), AppError> {
+    let id = payload.id.clone();
+    let account = adapter.link_account(payload).await?;
+    let status = if account.id == id {
         StatusCode::CREATED
+    } else {
+        StatusCode::OK
+    };
+    Ok((status, Json(account)))
+}
+
+/// Identifies an account by its provider and the id the provider assigned it.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProviderAccountQuery {
+    /// Provider account `name`.
+    provider: String,
+    /// Provider account `id`.
+    provider_account_id: String,
+}
+
+#[debug_handler]
+pub async fn get_user_by_account(
+    Extension(adapter): Extension<AuthAdapter>,
+    AppQuery(params): AppQuery<ProviderAccountQuery>,
+) -> Result<Json<Option<user::Model>>, AppError> {
+    let user = adapter
+        .get_user_by_account(&params.provider, &params.provider_account_id)
+        .await?;
+    Ok(Json(user))
+}
+
+/// Look up one account by provider, or list every account linked to a user.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountSearchQuery {
+    /// List the accounts of this user.
+    user_id: Option<String>,
+    /// Provider account `name`.
+    provider: Option<String>,
+    /// Provider account `id`.
+    provider_account_id: Option<String>,
+    /// Include OAuth tokens when listing. They are blanked by default.
+    #[serde(alias = "include_secrets", default)]
+    include_secrets: bool,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum AccountResult {
+    Single(Box<AccountWithExpiry>),
+    Multiple(Vec<AccountWithExpiry>),
+}
+
+pub async fn get_account(
+    Extension(adapter): Extension<AuthAdapter>,
+    AppQuery(params): AppQuery<AccountSearchQuery>,
+) -> Result<Json<Option<AccountResult>>, AppError> {
+    if let Some(user_id) = params.user_id {
+        let accounts = adapter
+            .list_user_accounts(&user_id, params.include_secrets)
+            .await?;
+        return Ok(Json(Some(AccountResult::Multiple(accounts))));
     }
+
+    let provider = params.provider.ok_or_else(|| missing_param("provider"))?;
+    let provider_account_id = params
+        .provider_account_id
+        .ok_or_else(|| missing_param("providerAccountId"))?;
+    let account = adapter.get_account(&provider, &provider_account_id).await?;
+    Ok(Json(
+        account.map(|account| AccountResult::Single(Box::new(account))),
+    ))
+}
+
+#[debug_handler]
+pub async fn update_account(
+    Extension(adapter): Extension<AuthAdapter>,
+    AppQuery(params): AppQuery<ProviderAccountQuery>,
+    AppJson(payload): AppJson<AccountTokenUpdate>,
+) -> Result<Json<account::Model>, AppError> {
+    let account = adapter
+        .update_account_tokens(&params.provider, &params.provider_account_id, payload)
+        .await?;
+    Ok(Json(account))
 }
 
 pub async fn delete_account(
-    State(state): State<Arc<DatabaseConnection>>,
-    Query(query): Query<HashMap<String, String>>,
-) -> impl IntoResponse {
-    if let Some(Some((id, name))) = query
-        .get("id")
-        .map(|id| query.get("name").map(|name| (id, name)))
-    {
-        if let Ok(Some(account)) = account::Entity::find()
-            .filter(account::Column::ProviderAccountId.eq(id))
-            .filter(account::Column::Provider.eq(name))
-            .one(&*state)
-            .await
-        {
-            if let Err(e) = account.delete(&*state).await {
-                eprintln!("{e}");
-                return StatusCode::INTERNAL_SERVER_ERROR;
-            }
-            StatusCode::OK
-        } else {
-            StatusCode::NOT_FOUND
-        }
-    } else {
-        eprintln!("No parameters provided");
-        StatusCode::UNPROCESSABLE_ENTITY
-    }
+    Extension(adapter): Extension<AuthAdapter>,
+    AppQuery(params): AppQuery<ProviderAccountQuery>,
+) -> Result<StatusCode, AppError> {
+    adapter
+        .delete_account(&params.provider, &params.provider_account_id)
+        .await?;
+    Ok(StatusCode::OK)
+}
+
+/// An account to unlink and the user it must belong to.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UnlinkAccount {
+    #[serde(deserialize_with = "entities::id::deserialize")]
+    user_id: String,
+    provider: String,
+    #[serde(deserialize_with = "entities::id::deserialize")]
+    provider_account_id: String,
+}
+
+/// Unlinks a provider account only if it belongs to `userId`. An account linked
+/// to someone else is reported as missing, so its existence isn't revealed.
+pub async fn unlink_account(
+    Extension(adapter): Extension<AuthAdapter>,
+    AppJson(payload): AppJson<UnlinkAccount>,
+) -> Result<StatusCode, AppError> {
+    adapter
+        .unlink_account(
+            &payload.user_id,
+            &payload.provider,
+            &payload.provider_account_id,
+        )
+        .await?;
+    Ok(StatusCode::OK)
+}
+
+/// A new session. Without `expires` it lasts for `SESSION_MAX_AGE_SECS`.
+///
+/// `expires` may be an RFC 3339 string or Unix epoch seconds or milliseconds.
+#[derive(Debug, Deserialize)]
+pub struct CreateSession {
+    #[serde(deserialize_with = "entities::id::deserialize")]
+    id: String,
+    session_token: String,
+    #[serde(deserialize_with = "entities::id::deserialize")]
+    user_id: String,
+    #[serde(default, deserialize_with = "timestamp::deserialize_option")]
+    expires: Option<DateTimeUtc>,
 }
 
 #[debug_handler]
 pub async fn create_session(
-    State(state): State<Arc<DatabaseConnection>>,
-    Json(payload): Json<session::Model>,
-) -> Result<Json<Session>, StatusCode> {
-    let item: session::ActiveModel = payload.into();
-
-    match item.insert(&*state).await {
-        Ok(value) => Ok(Json(value)),
-        Err(e) => {
-            eprintln!("{e}");
-            Err(StatusCode::INTERNAL_SERVER_ERROR)
-        }
-    }
+    Extension(adapter): Extension<AuthAdapter>,
+    Extension(config): Extension<Arc<Config>>,
+    AppJson(payload): AppJson<CreateSession>,
+) -> Result<Json<Session>, AppError> {
+    let session = Session {
+        id: payload.id,
+        session_token: payload.session_token,
+        user_id: payload.user_id,
+        expires: payload
+            .expires
+            .unwrap_or_else(|| Utc::now() + config.session_max_age),
+    };
+    Ok(Json(adapter.create_session(session).await?))
 }
 
-#[derive(Deserialize, Serialize)]
-pub struct UserAndSession {
-    pub user: user::Model,
-    pub session: session::Model,
+/// Selects one session by its token.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionTokenQuery {
+    session_token: String,
 }
 
+/// Counts session validations in `session_lookup_total`, labelled `hit`,
+/// `miss` or `expired`. In read-only maintenance an expired session is
+/// reported but not deleted.
 #[debug_handler]
 pub async fn get_session_and_user(
-    Query(query): Query<HashMap<String, String>>,
-    State(state): State<Arc<DatabaseConnection>>,
-) -> Result<Json<UserAndSession>, StatusCode> {
-    if let Some(id) = query.get("sessionToken") {
-        if let Ok(Some(session)) = session::Entity::find()
-            .filter(session::Column::SessionToken.eq(id))
-            .one(&*state)
-            .await
-        {
-            if let Ok(Some(user)) = user::Entity::find_by_id(&session.user_id)
-                .one(&*state)
-                .await
-            {
-                Ok(Json(UserAndSession { user, session }))
-            } else {
-                Err(StatusCode::NO_CONTENT)
-            }
-        } else {
-            Err(StatusCode::NO_CONTENT)
-        }
+    AppQuery(query): AppQuery<SessionTokenQuery>,
+    Extension(adapter): Extension<AuthAdapter>,
+    read_only: Option<Extension<ReadOnlyRequest>>,
+) -> Result<Json<Option<UserAndSession>>, AppError> {
+    let lookup = if read_only.is_some() {
+        adapter
+            .get_session_and_user_read_only(&query.session_token)
+            .await?
     } else {
-        eprintln!("No parameters provided");
-        Err(StatusCode::UNPROCESSABLE_ENTITY)
-    }
+        adapter.get_session_and_user(&query.session_token).await?
+    };
+    let (result, found) = match lookup {
+        SessionLookup::Hit(found) => ("hit", Some(*found)),
+        SessionLookup::Miss => ("miss", None),
+        SessionLookup::Expired => ("expired", None),
+    };
+    metrics::increment_counter!("session_lookup_total", "result" => result);
+    Ok(Json(found))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateSessionQuery {
+    session_token: String,
+    /// Replace the session's token with a freshly generated one.
+    #[serde(default)]
+    rotate: bool,
 }
 
+/// Updates a session from a JSON [`SessionUpdate`] body. With `rotate=true`
+/// the session also gets a fresh random token, so a leaked copy of the old one
+/// stops working.
 pub async fn update_session(
-    State(state): State<Arc<DatabaseConnection>>,
-    Query(query): Query<HashMap<String, String>>,
-    Form(form): Form<Session>,
-) -> impl IntoResponse {
-    println!("{query:#?}");
-    if let Some(id) = query.get("id") {
-        if let Ok(Some(session)) = session::Entity::find()
-            .filter(session::Column::SessionToken.eq(id))
-            .one(&*state)
-            .await
-        {
-            let mut session: session::ActiveModel = session.into();
-            session.user_id = Set(form.user_id);
-            session.expires = Set(form.expires);
-            session.session_token = Set(form.session_token);
-            if let Err(e) = session.update(&*state).await {
-                eprintln!("{e}");
-                StatusCode::INTERNAL_SERVER_ERROR
-            } else {
-                StatusCode::OK
-            }
-        } else {
-            StatusCode::NOT_FOUND
-        }
-    } else {
-        eprintln!("No parameters provided");
-        StatusCode::UNPROCESSABLE_ENTITY
-    }
+    Extension(adapter): Extension<AuthAdapter>,
+    AppQuery(query): AppQuery<UpdateSessionQuery>,
+    AppJson(form): AppJson<SessionUpdate>,
+) -> Result<Json<Session>, AppError> {
+    // the query carries the session token, so only the flag is logged
+    debug!(rotate = query.rotate, "updating session");
+    let session = adapter
+        .update_session(&query.session_token, form, query.rotate)
+        .await?;
+    Ok(Json(session))
 }
 
 pub async fn delete_session(
-    State(state): State<Arc<DatabaseConnection>>,
-    Query(query): Query<HashMap<String, String>>,
-) -> impl IntoResponse {
-    if let Some(id) = query.get("sessionToken") {
-        if let Ok(Some(session)) = session::Entity::find()
-            .filter(session::Column::SessionToken.eq(id))
-            .one(&*state)
-            .await
-        {
-            if let Err(e) = session.delete(&*state).await {
-                eprintln!("{e}");
-                return StatusCode::INTERNAL_SERVER_ERROR;
-            }
-            StatusCode::OK
-        } else {
-            StatusCode::NOT_FOUND
-        }
-    } else {
-        eprintln!("No parameters provided");
-        StatusCode::UNPROCESSABLE_ENTITY
-    }
+    Extension(adapter): Extension<AuthAdapter>,
+    AppQuery(query): AppQuery<SessionTokenQuery>,
+) -> Result<Json<Session>, AppError> {
+    Ok(Json(adapter.delete_session(&query.session_token).await?))
+}
+
+/// Selects every session belonging to one user.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserSessionsQuery {
+    user_id: String,
+}
+
+pub async fn list_user_sessions(
+    Extension(adapter): Extension<AuthAdapter>,
+    AppQuery(query): AppQuery<UserSessionsQuery>,
+) -> Result<Json<Vec<Session>>, AppError> {
+    Ok(Json(adapter.list_user_sessions(&query.user_id).await?))
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeletedCount {
+    pub deleted: u64,
+}
+
+/// Revokes all of a user's sessions, e.g. to sign them out everywhere.
+pub async fn delete_user_sessions(
+    Extension(adapter): Extension<AuthAdapter>,
+    tx: Tx,
+    AppQuery(query): AppQuery<UserSessionsQuery>,
+) -> Result<Json<DeletedCount>, AppError> {
+    let deleted = adapter.revoke_sessions(&query.user_id, None, &*tx).await?;
+    Ok(Json(DeletedCount { deleted }))
+}
+
+/// Sessions to revoke for "sign out everywhere else".
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct RevokeSessions {
+    #[serde(deserialize_with = "entities::id::deserialize")]
+    user_id: String,
+    /// The caller's current session, which is kept.
+    #[serde(alias = "except_token")]
+    except_token: Option<String>,
+}
+
+/// Revokes a user's sessions, optionally keeping the one matching `exceptToken`.
+pub async fn revoke_sessions(
+    Extension(adapter): Extension<AuthAdapter>,
+    tx: Tx,
+    AppJson(payload): AppJson<RevokeSessions>,
+) -> Result<Json<DeletedCount>, AppError> {
+    let deleted = adapter
+        .revoke_sessions(&payload.user_id, payload.except_token.as_deref(), &*tx)
+        .await?;
+    Ok(Json(DeletedCount { deleted }))
 }
 
 #[debug_handler]
 pub async fn create_verif_token(
-    State(state): State<Arc<DatabaseConnection>>,
-    Json(payload): Json<verification_token::Model>,
-) -> impl IntoResponse {
-    let item: verification_token::ActiveModel = payload.into();
-    if let Err(e) = item.insert(&*state).await {
-        eprintln!("{e}");
-        StatusCode::INTERNAL_SERVER_ERROR
-    } else {
-        StatusCode::CREATED
-    }
+    Extension(adapter): Extension<AuthAdapter>,
+    AppJson(payload): AppJson<verification_token::Model>,
+) -> Result<(StatusCode, Json<verification_token::Model>), AppError> {
+    let created = adapter.create_verification_token(payload).await?;
+    Ok((StatusCode::CREATED, Json(created)))
+}
+
+/// Selects a verification token by its `identifier`, passed as `id`.
+#[derive(Debug, Deserialize)]
+pub struct VerificationTokenQuery {
+    id: String,
 }
 
 pub async fn delete_verif_token(
-    State(state): State<Arc<DatabaseConnection>>,
-    Query(query): Query<HashMap<String, String>>,
-) -> Result<Json<verification_token::Model>, StatusCode> {
-    if let Some(id) = query.get("id") {
-        if let Ok(Some(verif_token)) = verification_token::Entity::find()
-            .filter(verification_token::Column::Identifier.eq(id))
-            .one(&*state)
-            .await
-        {
-            let return_value = verif_token.clone();
-            if let Err(e) = verif_token.delete(&*state).await {
-                eprintln!("{e}");
-                return Err(StatusCode::INTERNAL_SERVER_ERROR);
-            }
-            Ok(Json(return_value))
-        } else {
-            Err(StatusCode::NOT_FOUND)
-        }
-    } else {
-        eprintln!("No parameters provided");
-        Err(StatusCode::UNPROCESSABLE_ENTITY)
-    }
+    Extension(adapter): Extension<AuthAdapter>,
+    AppQuery(query): AppQuery<VerificationTokenQuery>,
+) -> Result<Json<verification_token::Model>, AppError> {
+    Ok(Json(adapter.delete_verification_token(&query.id).await?))
+}
+
+/// Consume a verification token. Both fields must match.
+#[derive(Debug, Deserialize)]
+pub struct UseVerificationToken {
+    identifier: String,
+    token: String,
+}
+
+#[debug_handler]
+pub async fn use_verif_token(
+    Extension(adapter): Extension<AuthAdapter>,
+    AppJson(payload): AppJson<UseVerificationToken>,
+) -> Result<Json<verification_token::Model>, AppError> {
+    let verif_token = adapter
+        .use_verification_token(&payload.identifier, &payload.token)
+        .await?;
+    Ok(Json(verif_token))
 }
@@ -0,0 +1,62 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::State,
+    http::{HeaderName, HeaderValue, Request},
+    middleware::Next,
+    response::Response,
+};
+use tracing::warn;
+
+pub type SecurityHeaders = Arc<[(HeaderName, HeaderValue)]>;
+
+/// Each header with the variable that overrides it and its default value.
+/// Setting a variable to an empty string turns that header off.
+const HEADERS: &[(&str, &str, &str)] = &[
+    (
+        "x-content-type-options",
+        "X_CONTENT_TYPE_OPTIONS",
+        "nosniff",
+    ),
+    ("x-frame-options", "X_FRAME_OPTIONS", "DENY"),
+    ("referrer-policy", "REFERRER_POLICY", "no-referrer"),
+    (
+        "content-security-policy",
+        "CONTENT_SECURITY_POLICY",
+        "default-src 'none'; frame-ancestors 'none'",
+    ),
+];
+
+pub fn from_env() -> SecurityHeaders {
+    HEADERS
+        .iter()
+        .filter_map(|&(name, key, default)| {
+            let value = std::env::var(key).unwrap_or_else(|_| default.to_owned());
+            if value.is_empty() {
+                return None;
+            }
+            match HeaderValue::from_str(&value) {
+                Ok(value) => Some((HeaderName::from_static(name), value)),
+                Err(_) => {
+                    warn!("ignoring invalid {key}: {value}");
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// Adds the security headers to every response that doesn't already set them.
+pub async fn set_security_headers<B>(
+    State(headers): State<SecurityHeaders>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let mut response = next.run(request).await;
+    for (name, value) in headers.iter() {
+        if !response.headers().contains_key(name) {
+            response.headers_mut().insert(name.clone(), value.clone());
+        }
+    }
+    response
+}
@@ -0,0 +1,203 @@
+use std::sync::Arc;
+
+use axum::{
+    body::Body,
+    error_handling::HandleErrorLayer,
+    extract::DefaultBodyLimit,
+    handler::Handler,
+    http::{
+        header::{AUTHORIZATION, CONTENT_TYPE, LINK},
+        HeaderName, HeaderValue, Method,
+    },
+    middleware,
+    routing::{delete, get, post},
+    BoxError, Extension, Router,
+};
+use sea_orm::DatabaseConnection;
+use tower::{timeout::error::Elapsed, ServiceBuilder};
+use tower_http::{
+    compression::CompressionLayer,
+    cors::CorsLayer,
+    trace::{DefaultOnResponse, TraceLayer},
+};
+use tracing::{warn, Level};
+
+use crate::{
+    adapter::AuthAdapter,
+    auth,
+    config::Config,
+    envelope,
+    error::AppError,
+    idempotency::{self, IdempotencyCache},
+    rate_limit, routes, security_headers, telemetry, token_encryption, tx,
+};
+
+/// Builds the adapter's router, configured from the environment.
+///
+/// The state is already applied, so embedders can mount the routes under
+/// their own prefix with `Router::new().nest("/auth", app(conn))`.
+pub fn app(state: Arc<DatabaseConnection>) -> Router {
+    let config = Arc::new(Config::from_env());
+    let mut adapter = AuthAdapter::new(Arc::clone(&state))
+        .with_slow_operation_threshold(config.slow_query)
+        .with_single_active_token(config.single_active_token);
+    if let Some(cipher) = token_encryption::from_env() {
+        adapter = adapter.with_token_cipher(Arc::new(cipher));
+    }
+
+    let api = Router::new()
+        .route(
+            "/users",
+            post(routes::create_user)
+                .get(routes::get_users)
+                .delete(routes::delete_user)
+                .put(routes::update_user),
+        )
+        .route("/users/bulk", post(routes::create_users_bulk))
+        .route("/users/:id", get(routes::get_user))
+        .route("/users/:id/full", get(routes::get_user_full))
+        .route(
+            "/accounts",
+            post(routes::create_account.layer(middleware::from_fn_with_state(
+                IdempotencyCache::new(
+                    config.idempotency_ttl,
+                    config.max_body_bytes,
+                    Arc::clone(&config.trusted_proxies),
+                ),
+                idempotency::replay,
+            )))
+            .get(routes::get_account)
+            .put(routes::update_account)
+            .delete(routes::delete_account),
+        )
+        .route("/accounts/user", get(routes::get_user_by_account))
+        .route("/accounts/unlink", delete(routes::unlink_account))
+        .route(
+            "/session",
+            post(routes::create_session)
+                .put(routes::update_session)
+                .delete(routes::delete_session),
+        )
+        .route(
+            "/sessions",
+            get(routes::list_user_sessions).delete(routes::delete_user_sessions),
+        )
+        .route("/sessions/revoke", delete(routes::revoke_sessions))
+        .route(
+            "/verification-token",
+            post(routes::create_verif_token).delete(routes::delete_verif_token),
+        )
+        .route("/verification-token/use", post(routes::use_verif_token))
+        .route("/session-user", get(routes::get_session_and_user))
+        .route("/health/detailed", get(routes::health_detailed))
+        // innermost, so a rejected request never opens a transaction
+        .route_layer(middleware::from_fn(tx::commit_on_success))
+        .route_layer(middleware::from_fn_with_state(
+            Arc::clone(&config.adapter_secret),
+            auth::require_api_key,
+        ))
+        // outside the auth check so key guessing is throttled too
+        .route_layer(middleware::from_fn_with_state(
+            rate_limit::limiter(
+                config.rate_limit_per_sec,
+                config.rate_limit_burst,
+                Arc::clone(&config.trusted_proxies),
+            ),
+            rate_limit::limit_mutations,
+        ));
+
+    let router = Router::new()
+        // kept for existing load balancer checks
+        .route("/health", get(routes::health_ready))
+        .route("/health/live", get(routes::health_live))
+        .route("/health/ready", get(routes::health_ready));
+    let router = match &config.base_path {
+        Some(base_path) => router.nest(base_path, api),
+        None => router.merge(api),
+    }
+    .fallback(routes::no_route);
+    let router = if config.log_bodies {
+        router.layer(middleware::from_fn(telemetry::log_bodies))
+    } else {
+        router
+    };
+    let router = router
+        // the handler future is dropped on timeout, cancelling its pending query
+        .layer(
+            ServiceBuilder::new()
+                .layer(HandleErrorLayer::new(handle_timeout))
+                .timeout(config.request_timeout),
+        )
+        .layer(DefaultBodyLimit::max(config.max_body_bytes));
+    // outside the timeout so its `504` is wrapped too, but inside compression
+    let router = if config.response_envelope {
+        router.layer(middleware::from_fn(envelope::wrap_responses))
+    } else {
+        router
+    };
+    let security_headers = Arc::clone(&config.security_headers);
+    router
+        // with both encodings off every response is sent as is
+        .layer(
+            CompressionLayer::new()
+                .gzip(config.enable_compression)
+                .br(config.enable_compression),
+        )
+        .layer(Extension(config))
+        .layer(Extension(adapter))
+        .route_layer(middleware::from_fn(telemetry::track_metrics))
+        .layer(middleware::from_fn_with_state(
+            security_headers,
+            security_headers::set_security_headers,
+        ))
+        .layer(cors_layer())
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(telemetry::request_span::<Body>)
+                .on_response(DefaultOnResponse::new().level(Level::INFO)),
+        )
+        .with_state(state)
+}
+
+async fn handle_timeout(err: BoxError) -> AppError {
+    if err.is::<Elapsed>() {
+        AppError::Timeout
+    } else {
+        AppError::Unavailable(err.to_string())
+    }
+}
+
+/// Builds the CORS policy from the comma-separated `ALLOWED_ORIGINS`.
+fn cors_layer() -> CorsLayer {
+    let Ok(allowed) = std::env::var("ALLOWED_ORIGINS") else {
+        warn!(
+            "ALLOWED_ORIGINS is not set, allowing any origin; this is insecure outside development"
+        );
+        return CorsLayer::permissive();
+    };
+
+    let origins: Vec<HeaderValue> = allowed
+        .split(',')
+        .map(str::trim)
+        .filter(|origin| !origin.is_empty())
+        .filter_map(|origin| match origin.parse() {
+            Ok(value) => Some(value),
+            Err(_) => {
+                warn!("ignoring invalid origin in ALLOWED_ORIGINS: {origin}");
+                None
+            }
+        })
+        .collect();
+
+    CorsLayer::new()
+        .allow_origin(origins)
+        .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
+        .allow_headers([
+            AUTHORIZATION,
+            CONTENT_TYPE,
+            HeaderName::from_static(auth::API_KEY_HEADER),
+            HeaderName::from_static(idempotency::IDEMPOTENCY_KEY_HEADER),
+        ])
+        // read by admin UIs paging through `GET /users`
+        .expose_headers([LINK, HeaderName::from_static("x-total-count")])
+}
@@ -0,0 +1,35 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use axum::{extract::State, http::Request, middleware::Next, response::Response};
+
+/// Number of requests currently being handled.
+#[derive(Clone, Default)]
+pub struct InFlight(Arc<AtomicUsize>);
+
+impl InFlight {
+    pub fn count(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Decrements the counter even if the request future is dropped mid-flight.
+struct Guard(Arc<AtomicUsize>);
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+pub async fn track_in_flight<B>(
+    State(in_flight): State<InFlight>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    in_flight.0.fetch_add(1, Ordering::Relaxed);
+    let _guard = Guard(in_flight.0);
+    next.run(request).await
+}
@@ -0,0 +1,203 @@
+use std::time::Instant;
+
+use axum::{
+    body::{self, Body, Bytes, Full, HttpBody},
+    extract::MatchedPath,
+    http::{HeaderMap, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+use opentelemetry::{global, propagation::Extractor};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{propagation::TraceContextPropagator, runtime};
+use serde_json::Value;
+use tracing::{error, info, info_span, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer};
+
+const REQUEST_DURATION: &str = "http_request_duration_seconds";
+const DURATION_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+pub fn install_recorder() -> PrometheusHandle {
+    PrometheusBuilder::new()
+        .set_buckets_for_metric(Matcher::Full(REQUEST_DURATION.to_owned()), DURATION_BUCKETS)
+        .expect("failed to set histogram buckets")
+        .install_recorder()
+        .expect("failed to install metrics recorder")
+}
+
+/// Records request counts and latencies labelled by method, route and status.
+pub async fn track_metrics<B>(request: Request<B>, next: Next<B>) -> Response {
+    let start = Instant::now();
+    let path = match request.extensions().get::<MatchedPath>() {
+        Some(path) => path.as_str().to_owned(),
+        None => request.uri().path().to_owned(),
+    };
+    let method = request.method().to_string();
+
+    let response = next.run(request).await;
+
+    let latency = start.elapsed().as_secs_f64();
+    let labels = [
+        ("method", method),
+        ("path", path),
+        ("status", response.status().as_u16().to_string()),
+    ];
+    metrics::increment_counter!("http_requests_total", &labels);
+    metrics::histogram!(REQUEST_DURATION, latency, &labels);
+
+    response
+}
+
+/// Installs the global subscriber. Logs are JSON lines with `LOG_FORMAT=json`.
+/// Spans are also exported over OTLP when `OTEL_EXPORTER_OTLP_ENDPOINT` is set.
+pub fn init_tracing() -> anyhow::Result<()> {
+    let filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::new("auth_adapter=info,tower_http=info"));
+    let fmt = match std::env::var("LOG_FORMAT").as_deref() {
+        // one object per line, with the request span's fields as keys
+        Ok("json") => tracing_subscriber::fmt::layer()
+            .json()
+            .with_current_span(true)
+            .with_span_list(false)
+            .boxed(),
+        _ => tracing_subscriber::fmt::layer().boxed(),
+    };
+    let otel = match std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT") {
+        Ok(endpoint) => {
+            global::set_text_map_propagator(TraceContextPropagator::new());
+            let tracer = opentelemetry_otlp::new_pipeline()
+                .tracing()
+                .with_exporter(
+                    opentelemetry_otlp::new_exporter()
+                        .tonic()
+                        .with_endpoint(endpoint),
+                )
+                .install_batch(runtime::Tokio)?;
+            Some(tracing_opentelemetry::layer().with_tracer(tracer))
+        }
+        Err(_) => None,
+    };
+    tracing_subscriber::registry()
+        .with(fmt)
+        .with(otel)
+        .with(filter)
+        .init();
+    Ok(())
+}
+
+/// Span for each request. Only the path is recorded, because query strings
+/// carry emails and session tokens. An incoming `traceparent` becomes the
+/// span's parent, linking it to the caller's trace.
+pub fn request_span<B>(request: &Request<B>) -> Span {
+    let span = info_span!(
+        "request",
+        method = %request.method(),
+        path = %request.uri().path(),
+    );
+    let parent = global::get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(request.headers()))
+    });
+    span.set_parent(parent);
+    span
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+/// Fields whose values are replaced before a body is logged, compared without
+/// case or underscores so both `session_token` and `sessionToken` match.
+const REDACTED_FIELDS: &[&str] = &[
+    "email",
+    // a verification token's identifier is the email it was sent to
+    "identifier",
+    "accesstoken",
+    "refreshtoken",
+    "sessiontoken",
+    "idtoken",
+    "token",
+];
+
+/// Bodies larger than this, or of unknown length, are not logged.
+const MAX_LOGGED_BODY_BYTES: usize = 64 * 1024;
+
+/// Logs request and response bodies with sensitive fields redacted. Only
+/// installed when `LOG_BODIES` is enabled.
+pub async fn log_bodies(request: Request<Body>, next: Next<Body>) -> Response {
+    let (parts, body) = request.into_parts();
+    let request = if loggable(&body) {
+        let bytes = match hyper::body::to_bytes(body).await {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                error!("failed to read request body: {e}");
+                return StatusCode::BAD_REQUEST.into_response();
+            }
+        };
+        info!(body = %redacted(&bytes), "request body");
+        Request::from_parts(parts, Body::from(bytes))
+    } else {
+        Request::from_parts(parts, body)
+    };
+
+    let response = next.run(request).await;
+    if !loggable(response.body()) {
+        return response;
+    }
+    let (parts, body) = response.into_parts();
+    let bytes = match hyper::body::to_bytes(body).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            error!("failed to read response body: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    info!(body = %redacted(&bytes), "response body");
+    Response::from_parts(parts, body::boxed(Full::from(bytes)))
+}
+
+fn loggable<B: HttpBody>(body: &B) -> bool {
+    body.size_hint()
+        .exact()
+        .is_some_and(|len| len > 0 && len <= MAX_LOGGED_BODY_BYTES as u64)
+}
+
+/// The body as JSON with sensitive fields replaced by `***`. Anything that
+/// isn't JSON, such as a form, is summarised by its size instead.
+fn redacted(bytes: &Bytes) -> String {
+    match serde_json::from_slice::<Value>(bytes) {
+        Ok(mut value) => {
+            redact(&mut value);
+            value.to_string()
+        }
+        Err(_) => format!("<{} bytes, not JSON>", bytes.len()),
+    }
+}
+
+fn redact(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                let key = key.replace('_', "").to_lowercase();
+                if REDACTED_FIELDS.contains(&key.as_str()) && !value.is_null() {
+                    *value = Value::String("***".to_owned());
+                } else {
+                    redact(value);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
@@ -0,0 +1,221 @@
+//! Optional encryption at rest for accounts' OAuth tokens.
+//!
+//! With `TOKEN_ENCRYPTION_KEY` set, `access_token`, `refresh_token` and
+//! `id_token` are sealed with AES-256-GCM before they're written and opened
+//! again when read. The ID token is a signed JWT carrying the user's identity
+//! claims, so it is as sensitive as the other two.
+//!
+//! A sealed token is [`PREFIX`] followed by the base64 of a key version byte,
+//! the nonce and the ciphertext. To rotate keys, give the new key a higher
+//! `TOKEN_ENCRYPTION_KEY_VERSION` and keep the old one in
+//! `TOKEN_ENCRYPTION_OLD_KEYS` until its tokens have been rewritten.
+//!
+//! Tokens stored before encryption was turned on are read back as they are.
+
+use std::{collections::HashMap, fmt};
+
+use base64::{engine::general_purpose::STANDARD_NO_PAD, Engine};
+use entities::account;
+use rand::Rng;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use sea_orm::DbErr;
+
+use crate::error::AppError;
+
+/// Marks a sealed token. OAuth tokens are printable ASCII (RFC 6749's
+/// `VSCHAR`) and ID tokens are JWTs, so none can start with a control
+/// character and a plaintext token is never mistaken for a sealed one.
+pub const PREFIX: &str = "\u{1}enc:";
+
+/// Keys for sealing and opening tokens, by version.
+pub struct TokenCipher {
+    version: u8,
+    keys: HashMap<u8, LessSafeKey>,
+}
+
+/// Why a stored token couldn't be opened.
+#[derive(Debug)]
+pub enum OpenError {
+    /// The token was sealed with a key that isn't configured.
+    UnknownVersion(u8),
+    /// The token isn't valid base64, is truncated, or fails authentication.
+    Malformed,
+}
+
+impl fmt::Display for OpenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OpenError::UnknownVersion(version) => {
+                write!(f, "token was sealed with unknown key version {version}")
+            }
+            OpenError::Malformed => f.write_str("token is corrupt or was sealed with another key"),
+        }
+    }
+}
+
+impl TokenCipher {
+    /// Seals new tokens with `key` and tags them with `version`.
+    pub fn new(version: u8, key: &[u8; 32]) -> Self {
+        Self {
+            version,
+            keys: HashMap::from([(version, less_safe_key(key))]),
+        }
+    }
+
+    /// Keeps opening tokens sealed with a retired key.
+    pub fn with_old_key(mut self, version: u8, key: &[u8; 32]) -> Self {
+        self.keys
+            .entry(version)
+            .or_insert_with(|| less_safe_key(key));
+        self
+    }
+
+    /// Encrypts `token`. `field` is authenticated with it, so a sealed access
+    /// token can't be passed off as a refresh token.
+    pub fn seal(&self, field: &str, token: &str) -> String {
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::thread_rng().fill(&mut nonce);
+        let mut ciphertext = token.as_bytes().to_vec();
+        self.keys[&self.version]
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(field),
+                &mut ciphertext,
+            )
+            .expect("token is too long to seal");
+
+        let mut sealed = Vec::with_capacity(1 + NONCE_LEN + ciphertext.len());
+        sealed.push(self.version);
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&ciphertext);
+        format!("{PREFIX}{}", STANDARD_NO_PAD.encode(sealed))
+    }
+
+    /// Decrypts a stored token. Tokens that were never sealed are returned as is.
+    pub fn open(&self, field: &str, stored: &str) -> Result<String, OpenError> {
+        let Some(encoded) = stored.strip_prefix(PREFIX) else {
+            return Ok(stored.to_owned());
+        };
+        let mut sealed = STANDARD_NO_PAD
+            .decode(encoded)
+            .map_err(|_| OpenError::Malformed)?;
+        if sealed.len() < 1 + NONCE_LEN {
+            return Err(OpenError::Malformed);
+        }
+        let version = sealed[0];
+        let key = self
+            .keys
+            .get(&version)
+            .ok_or(OpenError::UnknownVersion(version))?;
+        let nonce = Nonce::try_assume_unique_for_key(&sealed[1..1 + NONCE_LEN])
+            .map_err(|_| OpenError::Malformed)?;
+        let plaintext = key
+            .open_in_place(nonce, Aad::from(field), &mut sealed[1 + NONCE_LEN..])
+            .map_err(|_| OpenError::Malformed)?;
+        String::from_utf8(plaintext.to_vec()).map_err(|_| OpenError::Malformed)
+    }
+}
+
+impl fmt::Debug for TokenCipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // the keys themselves stay out of logs
+        f.debug_struct("TokenCipher")
+            .field("version", &self.version)
+            .finish_non_exhaustive()
+    }
+}
+
+fn less_safe_key(key: &[u8; 32]) -> LessSafeKey {
+    LessSafeKey::new(UnboundKey::new(&AES_256_GCM, key).expect("AES-256 keys are 32 bytes"))
+}
+
+/// Reads the keys from the environment. `None` leaves tokens in plaintext.
+///
+/// Panics on a malformed key rather than starting up unable to read or
+/// protect the stored tokens.
+pub fn from_env() -> Option<TokenCipher> {
+    let key = std::env::var("TOKEN_ENCRYPTION_KEY")
+        .ok()
+        .filter(|key| !key.is_empty())?;
+    let version = std::env::var("TOKEN_ENCRYPTION_KEY_VERSION")
+        .ok()
+        .filter(|version| !version.is_empty())
+        .map(|version| {
+            version
+                .parse()
+                .expect("TOKEN_ENCRYPTION_KEY_VERSION must be between 0 and 255")
+        })
+        .unwrap_or(1);
+    let mut cipher = TokenCipher::new(version, &decode_key(&key, "TOKEN_ENCRYPTION_KEY"));
+    let old_keys = std::env::var("TOKEN_ENCRYPTION_OLD_KEYS").unwrap_or_default();
+    for entry in old_keys.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let (version, key) = entry
+            .split_once(':')
+            .and_then(|(version, key)| Some((version.trim().parse().ok()?, key.trim())))
+            .expect("TOKEN_ENCRYPTION_OLD_KEYS entries must look like <version>:<base64 key>");
+        cipher = cipher.with_old_key(version, &decode_key(key, "TOKEN_ENCRYPTION_OLD_KEYS"));
+    }
+    Some(cipher)
+}
+
+fn decode_key(key: &str, var: &str) -> [u8; 32] {
+    base64::engine::general_purpose::STANDARD
+        .decode(key.trim())
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .unwrap_or_else(|| panic!("{var} must be 32 bytes encoded as base64"))
+}
+
+/// Seals a single token field for storage. Without a cipher it's stored as is.
+pub(crate) fn seal_field(
+    cipher: Option<&TokenCipher>,
+    field: &str,
+    token: Option<String>,
+) -> Option<String> {
+    match cipher {
+        Some(cipher) => token.map(|token| cipher.seal(field, &token)),
+        None => token,
+    }
+}
+
+/// Seals an account's tokens before it's written.
+pub(crate) fn seal_account(
+    cipher: Option<&TokenCipher>,
+    mut account: account::Model,
+) -> account::Model {
+    account.access_token = seal_field(cipher, "access_token", account.access_token);
+    account.refresh_token = seal_field(cipher, "refresh_token", account.refresh_token);
+    account.id_token = seal_field(cipher, "id_token", account.id_token);
+    account
+}
+
+/// Opens an account's tokens after it's read.
+pub(crate) fn open_account(
+    cipher: Option<&TokenCipher>,
+    mut account: account::Model,
+) -> Result<account::Model, AppError> {
+    account.access_token = open_field(cipher, "access_token", account.access_token)?;
+    account.refresh_token = open_field(cipher, "refresh_token", account.refresh_token)?;
+    account.id_token = open_field(cipher, "id_token", account.id_token)?;
+    Ok(account)
+}
+
+fn open_field(
+    cipher: Option<&TokenCipher>,
+    field: &str,
+    stored: Option<String>,
+) -> Result<Option<String>, AppError> {
+    let Some(stored) = stored else {
+        return Ok(None);
+    };
+    let opened = match cipher {
+        Some(cipher) => cipher.open(field, &stored).map_err(|e| e.to_string()),
+        None if stored.starts_with(PREFIX) => {
+            Err("token is encrypted but TOKEN_ENCRYPTION_KEY is not set".to_owned())
+        }
+        None => Ok(stored),
+    };
+    opened
+        .map(Some)
+        .map_err(|e| AppError::Database(DbErr::Custom(format!("{field}: {e}"))))
+}
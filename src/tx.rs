@@ -0,0 +1,105 @@
+//! Request-scoped database transactions.
+
+use std::{
+    ops::Deref,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts},
+    http::{request::Parts, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use sea_orm::{DatabaseConnection, DatabaseTransaction, DbErr, TransactionTrait};
+use tracing::warn;
+
+use crate::error::AppError;
+
+/// Where a handler's [`Tx`] is handed back to [`commit_on_success`].
+type Slot = Arc<Mutex<Option<DatabaseTransaction>>>;
+
+/// A transaction begun when the handler is called and finished by
+/// [`commit_on_success`]: committed if the response is `2xx`, rolled back
+/// otherwise. Handlers use it as a connection and never commit it themselves.
+///
+/// The transaction holds a pooled connection until the response is sent, so a
+/// handler taking `Tx` shouldn't query through the shared connection as well.
+///
+/// Handlers pass it to the [`AuthAdapter`](crate::AuthAdapter) methods that
+/// take a connection: deleting a user, a user's sessions, or every session but
+/// one. The adapter's other writes open their own transactions, because it is
+/// built without axum and must also work for library callers.
+pub struct Tx {
+    txn: Option<DatabaseTransaction>,
+    slot: Slot,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Tx
+where
+    Arc<DatabaseConnection>: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        // taken rather than cloned so a request can't open two transactions
+        let slot = parts.extensions.remove::<Slot>().ok_or_else(|| {
+            AppError::Database(DbErr::Custom(
+                "Tx extracted twice or without the commit_on_success layer".to_owned(),
+            ))
+            .into_response()
+        })?;
+        let conn = Arc::<DatabaseConnection>::from_ref(state);
+        let txn = conn
+            .begin()
+            .await
+            .map_err(|e| AppError::from(e).into_response())?;
+        Ok(Tx {
+            txn: Some(txn),
+            slot,
+        })
+    }
+}
+
+impl Deref for Tx {
+    type Target = DatabaseTransaction;
+
+    fn deref(&self) -> &DatabaseTransaction {
+        self.txn
+            .as_ref()
+            .expect("transaction is only taken on drop")
+    }
+}
+
+impl Drop for Tx {
+    fn drop(&mut self) {
+        if let Ok(mut slot) = self.slot.lock() {
+            *slot = self.txn.take();
+        }
+    }
+}
+
+/// Commits the transaction of a handler that took a [`Tx`] once it responds
+/// with a `2xx` status, and rolls it back otherwise. A failed commit replaces
+/// the response with the error, since the handler's writes didn't happen.
+pub async fn commit_on_success<B>(mut request: Request<B>, next: Next<B>) -> Response {
+    let slot = Slot::default();
+    request.extensions_mut().insert(Arc::clone(&slot));
+    let response = next.run(request).await;
+
+    let txn = slot.lock().ok().and_then(|mut slot| slot.take());
+    let Some(txn) = txn else {
+        return response;
+    };
+    if response.status().is_success() {
+        if let Err(e) = txn.commit().await {
+            return AppError::from(e).into_response();
+        }
+    } else if let Err(e) = txn.rollback().await {
+        warn!("failed to roll back request transaction: {e}");
+    }
+    response
+}
@@ -0,0 +1,672 @@
+mod common;
+
+use std::{
+    io,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+#[cfg(unix)]
+use auth_adapter::uds;
+use auth_adapter::{
+    adapter::{NewUser, SessionLookup},
+    client_ip::{client_ip, TrustedProxies},
+    envelope,
+    idempotency::{self, IdempotencyCache},
+    maintenance::{self, Maintenance, Mode},
+    telemetry,
+    token_encryption::{self, OpenError, TokenCipher},
+    tx::{self, Tx},
+    AppError, AuthAdapter,
+};
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Path},
+    handler::Handler,
+    http::{
+        header::{ALLOW, CONTENT_TYPE, RETRY_AFTER},
+        Request, StatusCode,
+    },
+    middleware,
+    routing::post,
+    Router,
+};
+use chrono::Utc;
+use entities::{account, session, user};
+use migration::{Migrator, MigratorTrait};
+use sea_orm::{
+    ActiveModelTrait, ConnectOptions, Database, DatabaseConnection, EntityTrait, Set,
+    TransactionTrait,
+};
+use tower::ServiceExt;
+
+/// Opens a freshly migrated in-memory SQLite database.
+async fn test_db() -> DatabaseConnection {
+    let mut options = ConnectOptions::new("sqlite::memory:".to_owned());
+    // every pooled connection would otherwise open its own empty database
+    options.max_connections(1).sqlx_logging(false);
+    let conn = Database::connect(options).await.unwrap();
+    Migrator::up(&conn, None).await.unwrap();
+    conn
+}
+
+async fn test_app() -> Router {
+    common::app_for(test_db().await)
+}
+
+#[tokio::test]
+async fn health_is_public() {
+    common::health_is_public(&test_app().await).await;
+}
+
+#[tokio::test]
+async fn rejects_requests_without_api_key() {
+    common::rejects_requests_without_api_key(&test_app().await).await;
+}
+
+#[tokio::test]
+async fn user_lifecycle() {
+    common::user_lifecycle(&test_app().await).await;
+}
+
+#[tokio::test]
+async fn searches_users_by_substring() {
+    common::searches_users_by_substring(&test_app().await).await;
+}
+
+#[tokio::test]
+async fn rejects_invalid_email() {
+    common::rejects_invalid_email(&test_app().await).await;
+}
+
+#[tokio::test]
+async fn account_lifecycle() {
+    common::account_lifecycle(&test_app().await).await;
+}
+
+#[tokio::test]
+async fn rejects_linking_account_of_another_user() {
+    common::rejects_linking_account_of_another_user(&test_app().await).await;
+}
+
+#[tokio::test]
+async fn accepts_numeric_ids() {
+    common::accepts_numeric_ids(&test_app().await).await;
+}
+
+#[tokio::test]
+async fn session_lifecycle() {
+    common::session_lifecycle(&test_app().await).await;
+}
+
+#[tokio::test]
+async fn revokes_other_sessions() {
+    common::revokes_other_sessions(&test_app().await).await;
+}
+
+#[tokio::test]
+async fn fetches_user_with_accounts_and_sessions() {
+    common::fetches_user_with_accounts_and_sessions(&test_app().await).await;
+}
+
+#[tokio::test]
+async fn duplicate_user_email_conflicts() {
+    common::duplicate_user_email_conflicts(&test_app().await).await;
+}
+
+#[tokio::test]
+async fn duplicate_session_token_conflicts() {
+    common::duplicate_session_token_conflicts(&test_app().await).await;
+}
+
+#[tokio::test]
+async fn accepts_expires_in_several_formats() {
+    common::accepts_expires_in_several_formats(&test_app().await).await;
+}
+
+#[tokio::test]
+async fn expired_session_is_deleted_on_read() {
+    common::expired_session_is_deleted_on_read(&test_app().await).await;
+}
+
+#[tokio::test]
+async fn verification_token_lifecycle() {
+    common::verification_token_lifecycle(&test_app().await).await;
+}
+
+#[tokio::test]
+async fn missing_query_parameter_is_a_bad_request() {
+    let app = test_app().await;
+    let (status, body) = common::call(&app, "DELETE", "/session", None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "invalid_query");
+    assert!(
+        body["error"].as_str().unwrap().contains("sessionToken"),
+        "{body}"
+    );
+}
+
+#[tokio::test]
+async fn rejects_oversized_bulk_request() {
+    let app = test_app().await;
+    let users = serde_json::Value::Array(vec![serde_json::json!({}); 1001]);
+    let (status, body) = common::call(&app, "POST", "/users/bulk", Some(users)).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert!(body["error"].as_str().unwrap().contains("1000"), "{body}");
+}
+
+#[tokio::test]
+async fn unsupported_method_lists_allowed_methods() {
+    let app = test_app().await;
+    let request = Request::patch("/users")
+        .header("x-api-key", common::SECRET)
+        .body(Body::empty())
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+    let allow = response.headers()[ALLOW].to_str().unwrap();
+    for method in ["GET", "POST", "PUT", "DELETE"] {
+        assert!(allow.contains(method), "{allow}");
+    }
+}
+
+#[tokio::test]
+async fn spoofed_forwarded_for_does_not_evade_rate_limit() {
+    let app = test_app().await;
+    let peer = SocketAddr::from(([203, 0, 113, 7], 50000));
+    let mut statuses = Vec::new();
+    for i in 0..30 {
+        let request = Request::post("/users")
+            .header("x-forwarded-for", format!("10.0.0.{i}"))
+            .extension(ConnectInfo(peer))
+            .body(Body::empty())
+            .unwrap();
+        statuses.push(app.clone().oneshot(request).await.unwrap().status());
+    }
+    assert!(
+        statuses.contains(&StatusCode::TOO_MANY_REQUESTS),
+        "{statuses:?}"
+    );
+}
+
+#[tokio::test]
+async fn forwarded_for_without_peer_address_is_not_trusted() {
+    let app = test_app().await;
+    let mut statuses = Vec::new();
+    for i in 0..30 {
+        let request = Request::post("/users")
+            .header("x-forwarded-for", format!("10.0.0.{i}"))
+            .body(Body::empty())
+            .unwrap();
+        statuses.push(app.clone().oneshot(request).await.unwrap().status());
+    }
+    assert!(
+        statuses.contains(&StatusCode::TOO_MANY_REQUESTS),
+        "{statuses:?}"
+    );
+
+    let request = Request::get("/")
+        .header("x-forwarded-for", "198.51.100.4")
+        .body(())
+        .unwrap();
+    assert_eq!(client_ip(&request, &TrustedProxies::default()), None);
+    assert_eq!(
+        client_ip(&request, &TrustedProxies::parse("10.0.0.0/8, unix")),
+        Some([198, 51, 100, 4].into())
+    );
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn unix_socket_bind_replaces_only_stale_sockets() {
+    let dir = std::env::temp_dir().join(format!("auth-adapter-uds-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let file = dir.join("not-a-socket");
+    std::fs::write(&file, "keep me").unwrap();
+    let err = uds::UdsAccept::bind(&file).err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+    assert_eq!(std::fs::read_to_string(&file).unwrap(), "keep me");
+
+    // dropping a std listener leaves its socket file behind, like a crashed run
+    let socket = dir.join("stale.sock");
+    drop(std::os::unix::net::UnixListener::bind(&socket).unwrap());
+    drop(uds::UdsAccept::bind(&socket).unwrap());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn read_only_maintenance_rejects_writes() {
+    let maintenance = Maintenance::from_env();
+    maintenance.set(Mode::ReadOnly);
+    let app = test_app().await.layer(middleware::from_fn_with_state(
+        maintenance.clone(),
+        maintenance::reject_during_maintenance,
+    ));
+
+    let request = Request::get("/users?email=nobody@example.com")
+        .header("x-api-key", common::SECRET)
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let request = Request::post("/users")
+        .header("x-api-key", common::SECRET)
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert!(response.headers().contains_key(RETRY_AFTER));
+
+    let request = Request::get("/health").body(Body::empty()).unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // reading an expired session leaves it in place until writes are back
+    maintenance.set(Mode::Off);
+    let user_id = common::create_user(&app, "alice@example.com").await;
+    let stale = serde_json::json!({
+        "id": "s1",
+        "session_token": "stale",
+        "user_id": user_id,
+        "expires": "2000-01-01T00:00:00+00:00",
+    });
+    let (status, _) = common::call(&app, "POST", "/session", Some(stale)).await;
+    assert_eq!(status, StatusCode::OK);
+    maintenance.set(Mode::ReadOnly);
+    let (status, body) = common::call(&app, "GET", "/session-user?sessionToken=stale", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.is_null());
+    maintenance.set(Mode::Off);
+    let (status, _) = common::call(&app, "DELETE", "/session?sessionToken=stale", None).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn envelope_wraps_bodies_and_errors() {
+    let app = test_app().await;
+    common::create_user(&app, "alice@example.com").await;
+    let app = app.layer(middleware::from_fn(envelope::wrap_responses));
+
+    let (status, body) = common::call(&app, "GET", "/users?limit=1", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["data"][0]["email"], "alice@example.com");
+    assert_eq!(body["meta"]["total"], 1);
+    assert_eq!(body["meta"]["next_cursor"], body["data"][0]["id"]);
+
+    let (status, body) = common::call(&app, "DELETE", "/users?id=missing", None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["errors"][0]["status"], "404");
+    assert_eq!(body["errors"][0]["code"], "not_found");
+}
+
+#[tokio::test]
+async fn request_transaction_commits_only_on_success() {
+    async fn insert_user(tx: Tx, Path(status): Path<u16>) -> StatusCode {
+        user::ActiveModel {
+            id: Set(format!("u{status}")),
+            ..Default::default()
+        }
+        .insert(&*tx)
+        .await
+        .unwrap();
+        StatusCode::from_u16(status).unwrap()
+    }
+
+    let conn = Arc::new(test_db().await);
+    let app = Router::new()
+        .route("/:status", post(insert_user))
+        .route_layer(middleware::from_fn(tx::commit_on_success))
+        .with_state(Arc::clone(&conn));
+    for status in [201, 409] {
+        let request = Request::post(format!("/{status}"))
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status().as_u16(), status);
+    }
+
+    let ids: Vec<String> = user::Entity::find()
+        .all(&*conn)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|user| user.id)
+        .collect();
+    assert_eq!(ids, ["u201"]);
+}
+
+#[test]
+fn token_cipher_opens_tokens_sealed_with_old_keys() {
+    let old = TokenCipher::new(1, &[1; 32]);
+    let sealed = old.seal("access_token", "secret");
+    assert!(sealed.starts_with(token_encryption::PREFIX), "{sealed}");
+    assert!(!sealed.contains("secret"));
+    assert_eq!(old.open("access_token", &sealed).unwrap(), "secret");
+    assert!(old.open("refresh_token", &sealed).is_err());
+    // tokens written before encryption was turned on pass through, even ones
+    // that look like an earlier marker
+    assert_eq!(old.open("access_token", "plain").unwrap(), "plain");
+    assert_eq!(old.open("access_token", "enc:plain").unwrap(), "enc:plain");
+
+    let rotated = TokenCipher::new(2, &[2; 32]).with_old_key(1, &[1; 32]);
+    assert_eq!(rotated.open("access_token", &sealed).unwrap(), "secret");
+    let resealed = rotated.seal("access_token", "secret");
+    assert!(matches!(
+        old.open("access_token", &resealed),
+        Err(OpenError::UnknownVersion(2))
+    ));
+}
+
+#[tokio::test]
+async fn account_tokens_are_sealed_at_rest() {
+    let conn = Arc::new(test_db().await);
+    let adapter = AuthAdapter::new(Arc::clone(&conn))
+        .with_token_cipher(Arc::new(TokenCipher::new(1, &[1; 32])));
+    let user = adapter.create_user(NewUser::default()).await.unwrap();
+    let account = account::Model {
+        id: "a1".to_owned(),
+        user_id: user.id,
+        r#type: "oauth".to_owned(),
+        provider: "github".to_owned(),
+        provider_account_id: "gh-1".to_owned(),
+        access_token: Some("access".to_owned()),
+        refresh_token: Some("refresh".to_owned()),
+        id_token: Some("header.claims.signature".to_owned()),
+        ..Default::default()
+    };
+    let linked = adapter.link_account(account).await.unwrap();
+    assert_eq!(linked.id_token.as_deref(), Some("header.claims.signature"));
+
+    let stored = account::Entity::find_by_id("a1")
+        .one(&*conn)
+        .await
+        .unwrap()
+        .unwrap();
+    for token in [stored.access_token, stored.refresh_token, stored.id_token] {
+        let token = token.unwrap();
+        assert!(token.starts_with(token_encryption::PREFIX), "{token}");
+    }
+
+    let read = adapter
+        .get_account("github", "gh-1")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(read.account.access_token.as_deref(), Some("access"));
+    assert_eq!(read.account.refresh_token.as_deref(), Some("refresh"));
+    assert_eq!(
+        read.account.id_token.as_deref(),
+        Some("header.claims.signature")
+    );
+}
+
+#[tokio::test]
+async fn idempotency_key_replays_only_the_same_body() {
+    let app = test_app().await;
+    let user_id = common::create_user(&app, "alice@example.com").await;
+    let link = |provider_account_id: &str| {
+        let account = serde_json::json!({
+            "id": "a1",
+            "user_id": user_id,
+            "type": "oauth",
+            "provider": "github",
+            "provider_account_id": provider_account_id,
+        });
+        Request::post("/accounts")
+            .header("x-api-key", common::SECRET)
+            .header("idempotency-key", "callback-1")
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(account.to_string()))
+            .unwrap()
+    };
+
+    let (status, first) = common::send(&app, link("gh-1")).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let (status, replayed) = common::send(&app, link("gh-1")).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(replayed, first);
+
+    let (status, body) = common::send(&app, link("gh-2")).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["code"], "validation_error");
+}
+
+#[tokio::test]
+async fn concurrent_requests_with_one_idempotency_key_run_once() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let handler = {
+        let calls = Arc::clone(&calls);
+        move || async move {
+            calls.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            StatusCode::CREATED
+        }
+    };
+    let cache = IdempotencyCache::new(Duration::from_secs(60), 1024, Arc::default());
+    let app = Router::new().route(
+        "/",
+        post(handler.layer(middleware::from_fn_with_state(cache, idempotency::replay))),
+    );
+    let request = |body: String| {
+        Request::post("/")
+            .header("idempotency-key", "k")
+            .body(Body::from(body))
+            .unwrap()
+    };
+
+    let (first, second) = tokio::join!(
+        app.clone().oneshot(request(String::new())),
+        app.clone().oneshot(request(String::new())),
+    );
+    assert_eq!(first.unwrap().status(), StatusCode::CREATED);
+    assert_eq!(second.unwrap().status(), StatusCode::CONFLICT);
+    let replayed = app.clone().oneshot(request(String::new())).await.unwrap();
+    assert_eq!(replayed.status(), StatusCode::CREATED);
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+    // oversized bodies are refused with the usual error body
+    let (status, body) = common::send(&app, request("x".repeat(2048))).await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(body["code"], "payload_too_large");
+}
+
+/// Collects everything a `tracing` subscriber writes.
+#[derive(Clone, Default)]
+struct CapturedLogs(Arc<Mutex<Vec<u8>>>);
+
+impl io::Write for CapturedLogs {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn logged_bodies_redact_emails_and_tokens() {
+    let logs = CapturedLogs::default();
+    let subscriber = tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::DEBUG)
+        .with_writer({
+            let logs = logs.clone();
+            move || logs.clone()
+        })
+        .finish();
+    let _guard = tracing::subscriber::set_default(subscriber);
+    // echoes the body back, so both the request and the response carry the secrets
+    let app = Router::new()
+        .route("/", post(|body: String| async move { body }))
+        .layer(middleware::from_fn(telemetry::log_bodies));
+
+    let token = serde_json::json!({
+        "id": 1,
+        "identifier": "alice@example.com",
+        "token": "magic-link-secret",
+        "expires": "2099-01-01T00:00:00+00:00",
+    });
+    let request = Request::post("/")
+        .body(Body::from(token.to_string()))
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let logged = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+    assert!(logged.contains("request body"), "{logged}");
+    assert!(logged.contains("response body"), "{logged}");
+    assert!(!logged.contains("alice@example.com"), "{logged}");
+    assert!(!logged.contains("magic-link-secret"), "{logged}");
+
+    // the session token travels in the query string of PUT /session
+    let app = test_app().await;
+    let user_id = common::create_user(&app, "bob@example.com").await;
+    let session = serde_json::json!({
+        "id": "s1",
+        "session_token": "session-secret",
+        "user_id": user_id,
+    });
+    let (status, _) = common::call(&app, "POST", "/session", Some(session)).await;
+    assert_eq!(status, StatusCode::OK);
+    let update = serde_json::json!({ "expires": "2099-01-01T00:00:00+00:00" });
+    let (status, _) = common::call(
+        &app,
+        "PUT",
+        "/session?sessionToken=session-secret",
+        Some(update),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let logged = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+    assert!(logged.contains("updating session"), "{logged}");
+    assert!(!logged.contains("session-secret"), "{logged}");
+}
+
+#[tokio::test]
+async fn database_errors_are_counted_per_operation() {
+    let metrics = telemetry::install_recorder();
+    // no migrations, so every query fails
+    let conn = Database::connect("sqlite::memory:").await.unwrap();
+    let adapter = AuthAdapter::new(Arc::new(conn));
+    assert!(matches!(
+        adapter.get_user("u1").await,
+        Err(AppError::Database(_))
+    ));
+    let rendered = metrics.render();
+    assert!(
+        rendered.contains(r#"db_errors_total{operation="get_user"} 1"#),
+        "{rendered}"
+    );
+}
+
+#[tokio::test]
+async fn slow_adapter_operations_are_logged() {
+    let logs = CapturedLogs::default();
+    let subscriber = tracing_subscriber::fmt()
+        .with_writer({
+            let logs = logs.clone();
+            move || logs.clone()
+        })
+        .finish();
+    let _guard = tracing::subscriber::set_default(subscriber);
+    let adapter =
+        AuthAdapter::new(Arc::new(test_db().await)).with_slow_operation_threshold(Duration::ZERO);
+
+    adapter
+        .get_user_by_email("alice@example.com")
+        .await
+        .unwrap();
+
+    let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+    assert!(
+        logs.contains("slow database operation: get_user_by_email took"),
+        "{logs}"
+    );
+}
+
+#[tokio::test]
+async fn adapter_runs_without_the_router() {
+    let conn = Arc::new(test_db().await);
+    let adapter = AuthAdapter::new(Arc::clone(&conn));
+    let user = adapter
+        .create_user(NewUser {
+            email: Some("Alice@Example.com".to_owned()),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    let found = adapter
+        .get_user_by_email("alice@example.com")
+        .await
+        .unwrap();
+    assert_eq!(found.map(|found| found.id), Some(user.id.clone()));
+
+    let account = account::Model {
+        id: "a1".to_owned(),
+        user_id: user.id.clone(),
+        r#type: "oauth".to_owned(),
+        provider: "github".to_owned(),
+        provider_account_id: "gh-1".to_owned(),
+        refresh_token: None,
+        access_token: Some("access".to_owned()),
+        expires_at: Some(1),
+        token_type: None,
+        scope: None,
+        id_token: None,
+        session_state: None,
+    };
+    adapter.link_account(account).await.unwrap();
+    let linked = adapter.get_user_by_account("github", "gh-1").await.unwrap();
+    assert_eq!(linked.map(|linked| linked.id), Some(user.id.clone()));
+    let account = adapter
+        .get_account("github", "gh-1")
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(account.is_expired);
+
+    let session = session::Model {
+        id: "s1".to_owned(),
+        session_token: "token-1".to_owned(),
+        user_id: user.id.clone(),
+        expires: Utc::now() + chrono::Duration::hours(1),
+    };
+    adapter.create_session(session).await.unwrap();
+    match adapter.get_session_and_user("token-1").await.unwrap() {
+        SessionLookup::Hit(found) => assert_eq!(found.user.id, user.id),
+        _ => panic!("session not found"),
+    }
+
+    assert!(matches!(
+        adapter
+            .use_verification_token("alice@example.com", "nope")
+            .await,
+        Err(AppError::NotFound)
+    ));
+
+    // the deletes join the caller's transaction, so rolling it back undoes them
+    let txn = conn.begin().await.unwrap();
+    let revoked = adapter.revoke_sessions(&user.id, None, &txn).await.unwrap();
+    assert_eq!(revoked, 1);
+    adapter.delete_user(&user.id, &txn).await.unwrap();
+    txn.rollback().await.unwrap();
+    assert!(adapter.get_user(&user.id).await.unwrap().is_some());
+    assert!(matches!(
+        adapter.get_session_and_user("token-1").await.unwrap(),
+        SessionLookup::Hit(_)
+    ));
+}
+
+#[tokio::test]
+async fn session_token_lookup_uses_index() {
+    common::session_token_lookup_uses_index(&test_db().await).await;
+}
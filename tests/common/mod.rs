@@ -0,0 +1,880 @@
+//! Helpers and API scenarios shared by the per-backend test suites.
+#![allow(dead_code)]
+
+use std::sync::Arc;
+
+use auth_adapter::app;
+use axum::{
+    body::Body,
+    http::{
+        header::{CACHE_CONTROL, CONTENT_TYPE, ETAG, IF_NONE_MATCH},
+        Request, StatusCode,
+    },
+    Router,
+};
+use chrono::Utc;
+use entities::{session, user};
+use migration::{Migrator, MigratorTrait};
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, ConnectionTrait, Database, DatabaseConnection, DbBackend,
+    EntityTrait, QueryFilter, QueryTrait, Set, Statement, TransactionTrait,
+};
+use serde_json::{json, Value};
+use tower::ServiceExt;
+
+pub const SECRET: &str = "test-secret";
+
+/// Builds the router against an already migrated database.
+pub fn app_for(conn: DatabaseConnection) -> Router {
+    std::env::set_var("ADAPTER_SECRET", SECRET);
+    app(Arc::new(conn))
+}
+
+/// Creates an empty database named `name` on the server behind `admin` and migrates it.
+pub async fn fresh_db(
+    admin: &DatabaseConnection,
+    base_url: &str,
+    name: &str,
+) -> DatabaseConnection {
+    let create = match admin.get_database_backend() {
+        DbBackend::MySql => format!("CREATE DATABASE `{name}`"),
+        DbBackend::Postgres | DbBackend::Sqlite => format!(r#"CREATE DATABASE "{name}""#),
+    };
+    admin.execute_unprepared(&create).await.unwrap();
+    let conn = Database::connect(format!("{base_url}/{name}"))
+        .await
+        .unwrap();
+    Migrator::up(&conn, None).await.unwrap();
+    conn
+}
+
+/// Defines a test that starts `$image` with testcontainers and runs each
+/// scenario against its own freshly migrated database on it. `$url` is the
+/// server URL with `{}` in place of the mapped `$port`, and `$admin_db` is a
+/// database that always exists there. The optional second list names checks
+/// that take the database connection rather than the router.
+#[allow(unused_macros)]
+macro_rules! container_scenarios {
+    (
+        $test:ident,
+        $image:expr,
+        $port:expr,
+        $url:literal,
+        $admin_db:literal,
+        [$($scenario:ident),* $(,)?]
+        $(, [$($check:ident),* $(,)?])? $(,)?
+    ) => {
+        #[tokio::test]
+        async fn $test() {
+            let docker = testcontainers::clients::Cli::default();
+            let node = docker.run($image);
+            let base_url = format!($url, node.get_host_port_ipv4($port));
+            let admin = sea_orm::Database::connect(format!("{base_url}/{}", $admin_db))
+                .await
+                .unwrap();
+            $(
+                let conn = common::fresh_db(&admin, &base_url, stringify!($scenario)).await;
+                common::$scenario(&common::app_for(conn)).await;
+            )*
+            $($(
+                let conn = common::fresh_db(&admin, &base_url, stringify!($check)).await;
+                common::$check(&conn).await;
+            )*)?
+        }
+    };
+}
+
+/// Checks that looking a session up by its token goes through
+/// `idx-session-session-token` instead of scanning the table.
+pub async fn session_token_lookup_uses_index(conn: &DatabaseConnection) {
+    let backend = conn.get_database_backend();
+    let lookup = session::Entity::find()
+        .filter(session::Column::SessionToken.eq("token-1"))
+        .build(backend);
+    let explain = |prefix: &str| {
+        Statement::from_sql_and_values(
+            backend,
+            format!("{prefix} {}", lookup.sql),
+            lookup.values.clone().unwrap().0,
+        )
+    };
+    let plan: Vec<String> = match backend {
+        DbBackend::Sqlite => conn
+            .query_all(explain("EXPLAIN QUERY PLAN"))
+            .await
+            .unwrap()
+            .iter()
+            .map(|row| row.try_get("", "detail").unwrap())
+            .collect(),
+        DbBackend::Postgres => {
+            // on an empty table a sequential scan is cheapest, so rule it out
+            // to see whether the planner can use the index at all
+            let txn = conn.begin().await.unwrap();
+            txn.execute_unprepared("SET LOCAL enable_seqscan = off")
+                .await
+                .unwrap();
+            let plan = txn
+                .query_all(explain("EXPLAIN"))
+                .await
+                .unwrap()
+                .iter()
+                .map(|row| row.try_get("", "QUERY PLAN").unwrap())
+                .collect();
+            txn.rollback().await.unwrap();
+            plan
+        }
+        DbBackend::MySql => {
+            // a unique lookup that matches nothing is answered without
+            // touching an index, so give it a row to find
+            let txn = conn.begin().await.unwrap();
+            let user = user::ActiveModel {
+                id: Set("u1".to_owned()),
+                ..Default::default()
+            }
+            .insert(&txn)
+            .await
+            .unwrap();
+            session::ActiveModel {
+                id: Set("s1".to_owned()),
+                session_token: Set("token-1".to_owned()),
+                user_id: Set(user.id),
+                expires: Set(Utc::now()),
+            }
+            .insert(&txn)
+            .await
+            .unwrap();
+            // `key` is the index MySQL chose for each table in the plan
+            let plan = txn
+                .query_all(explain("EXPLAIN"))
+                .await
+                .unwrap()
+                .iter()
+                .filter_map(|row| row.try_get::<Option<String>>("", "key").unwrap())
+                .collect();
+            txn.rollback().await.unwrap();
+            plan
+        }
+    };
+    assert!(
+        plan.iter()
+            .any(|step| step.contains("idx-session-session-token")),
+        "{plan:?}"
+    );
+}
+
+pub async fn send(app: &Router, request: Request<Body>) -> (StatusCode, Value) {
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let body = if bytes.is_empty() {
+        Value::Null
+    } else {
+        serde_json::from_slice(&bytes).unwrap()
+    };
+    (status, body)
+}
+
+pub async fn call(
+    app: &Router,
+    method: &str,
+    uri: &str,
+    body: Option<Value>,
+) -> (StatusCode, Value) {
+    let builder = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("x-api-key", SECRET);
+    let request = match body {
+        Some(body) => builder
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string())),
+        None => builder.body(Body::empty()),
+    };
+    send(app, request.unwrap()).await
+}
+
+/// Creates a user and returns the id the server assigned.
+pub async fn create_user(app: &Router, email: &str) -> String {
+    let (status, body) = call(
+        app,
+        "POST",
+        "/users",
+        Some(json!({ "name": "Alice", "email": email })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    body["id"].as_str().unwrap().to_owned()
+}
+
+pub async fn health_is_public(app: &Router) {
+    for path in ["/health", "/health/live", "/health/ready"] {
+        let request = Request::get(path).body(Body::empty()).unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK, "{path}");
+        assert_eq!(response.headers()["x-content-type-options"], "nosniff");
+    }
+}
+
+pub async fn rejects_requests_without_api_key(app: &Router) {
+    let request = Request::get("/users").body(Body::empty()).unwrap();
+    let (status, body) = send(app, request).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body["code"], "unauthorized");
+}
+
+pub async fn user_lifecycle(app: &Router) {
+    let (status, body) = call(
+        app,
+        "POST",
+        "/users",
+        Some(json!({ "name": "Alice", "email": "Alice@Example.com" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["email"], "alice@example.com");
+    let id = body["id"].as_str().unwrap().to_owned();
+    let uri = format!("/users?id={id}");
+    let user_uri = format!("/users/{id}");
+
+    let (status, body) = call(app, "GET", &user_uri, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["name"], "Alice");
+
+    let request = Request::get(&user_uri)
+        .header("x-api-key", SECRET)
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert!(response.headers()[CACHE_CONTROL]
+        .to_str()
+        .unwrap()
+        .starts_with("private"));
+    let etag = response.headers()[ETAG].clone();
+    let request = Request::get(&user_uri)
+        .header("x-api-key", SECRET)
+        .header(IF_NONE_MATCH, etag)
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+    let (status, body) = call(app, "GET", "/users?email=ALICE@example.com", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["name"], "Alice");
+
+    let (status, body) = call(app, "PUT", &uri, Some(json!({ "name": "Bob" }))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["name"], "Bob");
+    let (_, body) = call(app, "GET", &user_uri, None).await;
+    assert_eq!(body["name"], "Bob");
+    assert_eq!(body["email"], "alice@example.com");
+
+    let verified = json!({ "email_verified": "2024-01-01T00:00:00+00:00" });
+    let (_, body) = call(app, "PUT", &uri, Some(verified)).await;
+    assert!(body["email_verified"].is_string());
+    let (_, body) = call(app, "PUT", &uri, Some(json!({ "email_verified": null }))).await;
+    assert_eq!(body["email_verified"], Value::Null);
+
+    let profile = json!({ "locale": "en-GB", "timezone": "Europe/London", "handle": "bob" });
+    let (_, body) = call(app, "PUT", &uri, Some(profile)).await;
+    assert_eq!(body["locale"], "en-GB");
+    assert_eq!(body["handle"], "bob");
+    let (_, body) = call(app, "PUT", &uri, Some(json!({ "handle": null }))).await;
+    assert_eq!(body["handle"], Value::Null);
+    assert_eq!(body["timezone"], "Europe/London");
+
+    let (status, body) = call(app, "GET", "/users", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["data"].as_array().unwrap().len(), 1);
+    assert_eq!(body["next_cursor"], Value::Null);
+
+    let (status, body) = call(app, "DELETE", &uri, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["name"], "Bob");
+    let (status, body) = call(app, "GET", &user_uri, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, Value::Null);
+}
+
+pub async fn searches_users_by_substring(app: &Router) {
+    create_user(app, "alice@example.com").await;
+    create_user(app, "bob@example.org").await;
+
+    // every user is named Alice, so this has to match on the email
+    let (status, body) = call(app, "GET", "/users?q=EXAMPLE.COM", None).await;
+    assert_eq!(status, StatusCode::OK);
+    let users = body["data"].as_array().unwrap();
+    assert_eq!(users.len(), 1);
+    assert_eq!(users[0]["email"], "alice@example.com");
+
+    let (_, body) = call(app, "GET", "/users?q=example.", None).await;
+    assert_eq!(body["data"].as_array().unwrap().len(), 2);
+
+    // wildcards in the query match only themselves
+    let (_, body) = call(app, "GET", "/users?q=%25", None).await;
+    assert_eq!(body["data"].as_array().unwrap().len(), 0);
+
+    let (_, body) = call(app, "GET", "/users?emailVerified=false", None).await;
+    assert_eq!(body["data"].as_array().unwrap().len(), 2);
+    let (_, body) = call(app, "GET", "/users?email_verified=true", None).await;
+    assert_eq!(body["data"].as_array().unwrap().len(), 0);
+}
+
+pub async fn rejects_invalid_email(app: &Router) {
+    let (status, body) = call(
+        app,
+        "POST",
+        "/users",
+        Some(json!({ "email": "not-an-email" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["code"], "validation_error");
+
+    let (status, body) = call(
+        app,
+        "POST",
+        "/users?validate_only=true",
+        Some(json!({ "email": "alice@example.com" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["valid"], true);
+    create_user(app, "alice@example.com").await;
+    let (status, _) = call(
+        app,
+        "POST",
+        "/users?validate_only=true",
+        Some(json!({ "email": "alice@example.com" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    // server-assigned fields are rejected rather than silently ignored
+    let request = Request::post("/users")
+        .header("x-api-key", SECRET)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(
+            json!({ "id": "u1", "email": "a@example.com" }).to_string(),
+        ))
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    let request = Request::post("/users")
+        .header("x-api-key", SECRET)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(r#"{"email": "a@example.com",}"#))
+        .unwrap();
+    let (status, body) = send(app, request).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "malformed_body");
+    assert!(body["error"]
+        .as_str()
+        .unwrap()
+        .starts_with("invalid JSON at byte"));
+}
+
+pub async fn account_lifecycle(app: &Router) {
+    let user_id = create_user(app, "alice@example.com").await;
+
+    let account = json!({
+        "id": "a1",
+        "user_id": user_id,
+        "type": "oauth",
+        "provider": "github",
+        "provider_account_id": "gh-1",
+        "access_token": "old",
+        "expires_at": 1,
+    });
+    let (status, body) = call(app, "POST", "/accounts", Some(account.clone())).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(body["provider_account_id"], "gh-1");
+
+    // linking again refreshes the tokens on the existing row
+    let mut relinked = account;
+    relinked["id"] = json!("a2");
+    relinked["access_token"] = json!("relinked");
+    let (status, body) = call(app, "POST", "/accounts", Some(relinked)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["id"], "a1");
+    assert_eq!(body["access_token"], "relinked");
+
+    let query = "provider=github&providerAccountId=gh-1";
+    let (status, body) = call(app, "GET", &format!("/accounts/user?{query}"), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["id"], user_id);
+    let uri = "/users?provider=github&providerAccountId=gh-1";
+    let (status, body) = call(app, "GET", uri, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body[0]["id"], user_id);
+    // the snake_case spelling is still accepted
+    let uri = "/users?provider=github&provider_account_id=gh-1";
+    let (_, body) = call(app, "GET", uri, None).await;
+    assert_eq!(body[0]["id"], user_id);
+
+    let (status, body) = call(app, "GET", &format!("/accounts?{query}"), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["is_expired"], true);
+
+    let uri = format!("/accounts?userId={user_id}");
+    let (status, body) = call(app, "GET", &uri, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body[0]["provider"], "github");
+    assert_eq!(body[0]["access_token"], Value::Null);
+    let (_, body) = call(app, "GET", &format!("{uri}&includeSecrets=true"), None).await;
+    assert_eq!(body[0]["access_token"], "relinked");
+    // the snake_case spelling is still accepted
+    let (_, body) = call(app, "GET", &format!("{uri}&include_secrets=true"), None).await;
+    assert_eq!(body[0]["access_token"], "relinked");
+
+    let (status, body) = call(
+        app,
+        "PUT",
+        &format!("/accounts?{query}"),
+        Some(json!({ "access_token": "new", "expires_at": i32::MAX })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["access_token"], "new");
+    assert_eq!(body["type"], "oauth");
+
+    let (status, _) = call(app, "DELETE", &format!("/accounts?{query}"), None).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = call(app, "DELETE", &format!("/accounts?{query}"), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, body) = call(app, "GET", &format!("/accounts/user?{query}"), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, Value::Null);
+}
+
+pub async fn rejects_linking_account_of_another_user(app: &Router) {
+    let alice = create_user(app, "alice@example.com").await;
+    let mallory = create_user(app, "mallory@example.com").await;
+
+    let account = |id: &str, user_id: &str| {
+        json!({
+            "id": id,
+            "user_id": user_id,
+            "type": "oauth",
+            "provider": "google",
+            "provider_account_id": "g-1",
+        })
+    };
+    let (status, _) = call(app, "POST", "/accounts", Some(account("a1", &alice))).await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let (status, body) = call(app, "POST", "/accounts", Some(account("a2", &mallory))).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["code"], "account_already_linked");
+
+    let unlink = |user_id: &str| json!({ "userId": user_id, "provider": "google", "providerAccountId": "g-1" });
+    let (status, _) = call(app, "DELETE", "/accounts/unlink", Some(unlink(&mallory))).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = call(app, "DELETE", "/accounts/unlink", Some(unlink(&alice))).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+pub async fn accepts_numeric_ids(app: &Router) {
+    let user_id = create_user(app, "alice@example.com").await;
+    let account = |id: Value, provider_account_id: Value| {
+        json!({
+            "id": id,
+            "user_id": user_id,
+            "type": "oauth",
+            "provider": "github",
+            "provider_account_id": provider_account_id,
+        })
+    };
+
+    let (status, body) = call(
+        app,
+        "POST",
+        "/accounts",
+        Some(account(json!(7), json!(12345))),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(body["id"], "7");
+    assert_eq!(body["provider_account_id"], "12345");
+
+    let (status, body) = call(
+        app,
+        "POST",
+        "/accounts",
+        Some(account(json!("a 8"), json!(1))),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["code"], "validation_error");
+}
+
+pub async fn session_lifecycle(app: &Router) {
+    let user_id = create_user(app, "alice@example.com").await;
+
+    let (status, body) = call(
+        app,
+        "POST",
+        "/session",
+        Some(json!({
+            "id": "s1",
+            "session_token": "token-1",
+            "user_id": user_id,
+            "expires": "2099-01-01T02:00:00+02:00",
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["session_token"], "token-1");
+    assert_eq!(body["expires"], "2099-01-01T00:00:00Z");
+
+    // timestamps without an offset are ambiguous and rejected
+    let request = Request::post("/session")
+        .header("x-api-key", SECRET)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(
+            json!({
+                "id": "s2",
+                "session_token": "token-2",
+                "user_id": user_id,
+                "expires": "2099-01-01T00:00:00",
+            })
+            .to_string(),
+        ))
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    let (status, body) = call(app, "GET", "/session-user?sessionToken=token-1", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["user"]["id"], user_id);
+    assert_eq!(body["session"]["id"], "s1");
+
+    let (status, body) = call(
+        app,
+        "PUT",
+        "/session?sessionToken=token-1",
+        Some(json!({ "expires": "2098-01-01T00:00:00+00:00" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["session_token"], "token-1");
+
+    // a bad body gets the same structured error as every other route
+    let (status, body) = call(
+        app,
+        "PUT",
+        "/session?sessionToken=token-1",
+        Some(json!({ "expires": "next tuesday" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["code"], "validation_error");
+
+    let uri = "/session?sessionToken=token-1&rotate=true";
+    let (status, body) = call(app, "PUT", uri, Some(json!({}))).await;
+    assert_eq!(status, StatusCode::OK);
+    let rotated = body["session_token"].as_str().unwrap().to_owned();
+    assert_ne!(rotated, "token-1");
+    let (_, body) = call(app, "GET", "/session-user?sessionToken=token-1", None).await;
+    assert_eq!(body, Value::Null);
+
+    let (status, body) = call(app, "GET", &format!("/sessions?userId={user_id}"), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body.as_array().unwrap().len(), 1);
+
+    let uri = format!("/session?sessionToken={rotated}");
+    let (status, body) = call(app, "DELETE", &uri, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["session_token"], rotated);
+    assert!(body["expires"].as_str().unwrap().starts_with("2098-01-01"));
+
+    let (status, _) = call(app, "DELETE", &uri, None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, body) = call(app, "DELETE", &format!("/sessions?userId={user_id}"), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["deleted"], 0);
+
+    let uri = format!("/session-user?sessionToken={rotated}");
+    let (status, body) = call(app, "GET", &uri, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, Value::Null);
+}
+
+pub async fn revokes_other_sessions(app: &Router) {
+    let user_id = create_user(app, "alice@example.com").await;
+    for i in 1..=3 {
+        let session = json!({
+            "id": format!("s{i}"),
+            "session_token": format!("token-{i}"),
+            "user_id": user_id,
+        });
+        let (status, _) = call(app, "POST", "/session", Some(session)).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    let revoke = json!({ "userId": user_id, "exceptToken": "token-1" });
+    let (status, body) = call(app, "DELETE", "/sessions/revoke", Some(revoke)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["deleted"], 2);
+
+    let (_, body) = call(app, "GET", &format!("/sessions?userId={user_id}"), None).await;
+    assert_eq!(body.as_array().unwrap().len(), 1);
+    assert_eq!(body[0]["session_token"], "token-1");
+
+    for i in 4..=5 {
+        let session = json!({
+            "id": format!("s{i}"),
+            "session_token": format!("token-{i}"),
+            "user_id": user_id,
+        });
+        let (status, _) = call(app, "POST", "/session", Some(session)).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+    let revoke = json!({ "userId": user_id, "except_token": "token-4" });
+    let (status, body) = call(app, "DELETE", "/sessions/revoke", Some(revoke)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["deleted"], 2);
+    let (_, body) = call(app, "GET", &format!("/sessions?userId={user_id}"), None).await;
+    assert_eq!(body[0]["session_token"], "token-4");
+
+    let revoke = json!({ "userId": user_id, "exceptTokens": "token-4" });
+    let (status, _) = call(app, "DELETE", "/sessions/revoke", Some(revoke)).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+pub async fn fetches_user_with_accounts_and_sessions(app: &Router) {
+    let user_id = create_user(app, "alice@example.com").await;
+    let account = json!({
+        "id": "a1",
+        "user_id": user_id,
+        "type": "oauth",
+        "provider": "github",
+        "provider_account_id": "gh-1",
+        "access_token": "secret",
+    });
+    let (status, _) = call(app, "POST", "/accounts", Some(account)).await;
+    assert!(status.is_success());
+    for (id, expires) in [
+        ("s1", "2099-01-01T00:00:00Z"),
+        ("s2", "2000-01-01T00:00:00Z"),
+    ] {
+        let session = json!({
+            "id": id,
+            "session_token": format!("token-{id}"),
+            "user_id": user_id,
+            "expires": expires,
+        });
+        let (status, _) = call(app, "POST", "/session", Some(session)).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    let (status, body) = call(app, "GET", &format!("/users/{user_id}/full"), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["user"]["email"], "alice@example.com");
+    assert_eq!(body["accounts"].as_array().unwrap().len(), 1);
+    assert_eq!(body["accounts"][0]["provider"], "github");
+    assert!(body["accounts"][0]["access_token"].is_null(), "{body}");
+    assert_eq!(body["sessions"].as_array().unwrap().len(), 1);
+    assert_eq!(body["sessions"][0]["id"], "s1");
+
+    let (status, body) = call(app, "GET", "/users/missing/full", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.is_null());
+}
+
+pub async fn duplicate_user_email_conflicts(app: &Router) {
+    create_user(app, "alice@example.com").await;
+    let (status, body) = call(
+        app,
+        "POST",
+        "/users",
+        Some(json!({ "name": "Alice", "email": "ALICE@example.com" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["code"], "conflict");
+    assert_eq!(body["error"], "resource already exists");
+}
+
+pub async fn duplicate_session_token_conflicts(app: &Router) {
+    let user_id = create_user(app, "alice@example.com").await;
+
+    for (id, expected) in [("s1", StatusCode::OK), ("s2", StatusCode::CONFLICT)] {
+        let (status, body) = call(
+            app,
+            "POST",
+            "/session",
+            Some(json!({
+                "id": id,
+                "session_token": "token-1",
+                "user_id": user_id,
+                "expires": "2099-01-01T00:00:00+00:00",
+            })),
+        )
+        .await;
+        assert_eq!(status, expected);
+        if expected == StatusCode::CONFLICT {
+            assert_eq!(body["error"], "session token already exists");
+        }
+    }
+}
+
+pub async fn accepts_expires_in_several_formats(app: &Router) {
+    let user_id = create_user(app, "alice@example.com").await;
+    let formats = [
+        json!("2100-01-01T01:00:00+01:00"),
+        json!(4_102_444_800_i64),
+        json!(4_102_444_800_000_i64),
+        json!("4102444800"),
+    ];
+    for (i, expires) in formats.into_iter().enumerate() {
+        let (status, body) = call(
+            app,
+            "POST",
+            "/session",
+            Some(json!({
+                "id": format!("s{i}"),
+                "session_token": format!("token-{i}"),
+                "user_id": user_id,
+                "expires": expires,
+            })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{expires}: {body}");
+        assert_eq!(body["expires"], "2100-01-01T00:00:00Z", "{expires}");
+    }
+
+    let (status, body) = call(
+        app,
+        "POST",
+        "/verification-token",
+        Some(json!({
+            "id": 1,
+            "identifier": "alice@example.com",
+            "token": "magic",
+            "expires": 4_102_444_800_000_i64,
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(body["expires"], "2100-01-01T00:00:00Z");
+
+    let (status, body) = call(
+        app,
+        "POST",
+        "/session",
+        Some(json!({
+            "id": "garbage",
+            "session_token": "token-garbage",
+            "user_id": user_id,
+            "expires": "next tuesday",
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["code"], "validation_error");
+}
+
+pub async fn expired_session_is_deleted_on_read(app: &Router) {
+    let user_id = create_user(app, "alice@example.com").await;
+
+    let (status, _) = call(
+        app,
+        "POST",
+        "/session",
+        Some(json!({
+            "id": "s1",
+            "session_token": "stale",
+            "user_id": user_id,
+            "expires": "2000-01-01T00:00:00+00:00",
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, body) = call(app, "GET", "/session-user?sessionToken=stale", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, Value::Null);
+
+    let (status, _) = call(app, "DELETE", "/session?sessionToken=stale", None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+pub async fn verification_token_lifecycle(app: &Router) {
+    // MySQL keeps whole seconds
+    let before = Utc::now() - chrono::Duration::seconds(1);
+    let (status, body) = call(
+        app,
+        "POST",
+        "/verification-token",
+        Some(json!({
+            "id": 1,
+            "identifier": "alice@example.com",
+            "token": "magic",
+            "expires": "2099-01-01T00:00:00+00:00",
+            // ignored, the server sets it
+            "created_at": "2000-01-01T00:00:00+00:00",
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(body["token"], "magic");
+    let created_at: chrono::DateTime<Utc> =
+        serde_json::from_value(body["created_at"].clone()).unwrap();
+    assert!(created_at >= before, "{body}");
+
+    let (status, body) = call(
+        app,
+        "POST",
+        "/verification-token",
+        Some(json!({
+            "id": 2,
+            "identifier": "alice@example.com",
+            "token": "magic",
+            "expires": "2099-01-01T00:00:00+00:00",
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["code"], "conflict");
+    assert_eq!(body["error"], "resource already exists");
+
+    let (status, _) = call(
+        app,
+        "POST",
+        "/verification-token/use",
+        Some(json!({ "identifier": "alice@example.com", "token": "wrong" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let used = json!({ "identifier": "alice@example.com", "token": "magic" });
+    let (status, body) = call(app, "POST", "/verification-token/use", Some(used.clone())).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["token"], "magic");
+
+    let (status, _) = call(app, "POST", "/verification-token/use", Some(used)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, _) = call(
+        app,
+        "POST",
+        "/verification-token",
+        Some(json!({
+            "id": 3,
+            "identifier": "alice@example.com",
+            "token": "stale",
+            "expires": "2000-01-01T00:00:00+00:00",
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    let stale = json!({ "identifier": "alice@example.com", "token": "stale" });
+    let (status, body) = call(app, "POST", "/verification-token/use", Some(stale.clone())).await;
+    assert_eq!(status, StatusCode::GONE);
+    assert_eq!(body["code"], "gone");
+    let (status, _) = call(app, "POST", "/verification-token/use", Some(stale)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
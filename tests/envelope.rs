@@ -0,0 +1,53 @@
+//! Runs the router with `RESPONSE_ENVELOPE` enabled. Kept in its own test
+//! binary because the setting is read from the environment, which would leak
+//! into the other suites' tests running in parallel.
+
+mod common;
+
+use axum::{
+    body::Body,
+    http::{header::ALLOW, Request, StatusCode},
+    Router,
+};
+use migration::{Migrator, MigratorTrait};
+use sea_orm::{ConnectOptions, Database};
+use serde_json::json;
+use tower::ServiceExt;
+
+async fn enveloped_app() -> Router {
+    std::env::set_var("RESPONSE_ENVELOPE", "true");
+    std::env::set_var("MAX_BODY_BYTES", "1024");
+    let mut options = ConnectOptions::new("sqlite::memory:".to_owned());
+    options.max_connections(1).sqlx_logging(false);
+    let conn = Database::connect(options).await.unwrap();
+    Migrator::up(&conn, None).await.unwrap();
+    common::app_for(conn)
+}
+
+#[tokio::test]
+async fn errors_outside_the_handlers_are_enveloped() {
+    let app = enveloped_app().await;
+
+    let (status, body) = common::call(&app, "GET", "/no-such-route", None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["errors"][0]["status"], "404");
+    assert_eq!(body["errors"][0]["code"], "route_not_found");
+
+    let request = Request::patch("/users")
+        .header("x-api-key", common::SECRET)
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+    assert!(response.headers().contains_key(ALLOW));
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(body["errors"][0]["status"], "405");
+    assert_eq!(body["errors"][0]["code"], "method_not_allowed");
+
+    let email = format!("{}@example.com", "a".repeat(2048));
+    let (status, body) =
+        common::call(&app, "POST", "/users", Some(json!({ "email": email }))).await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(body["errors"][0]["code"], "payload_too_large");
+}
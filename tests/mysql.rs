@@ -0,0 +1,38 @@
+//! Runs the duplicate-key scenarios against a real MySQL started with
+//! testcontainers, since MySQL reports them with its own error numbers and
+//! has no conditional upsert for account links.
+//!
+//! Needs Docker. Run with `cargo test --features mysql-tests --test mysql`.
+#![cfg(feature = "mysql-tests")]
+
+#[macro_use]
+mod common;
+
+use testcontainers::{core::WaitFor, images::generic::GenericImage};
+
+/// testcontainers has no MySQL image, so this runs the official one with an
+/// empty root password.
+fn mysql() -> GenericImage {
+    GenericImage::new("mysql", "8.0")
+        .with_env_var("MYSQL_ALLOW_EMPTY_PASSWORD", "yes")
+        .with_exposed_port(3306)
+        // the temporary server run during initialisation reports port 0
+        .with_wait_for(WaitFor::message_on_stderr(
+            "port: 3306  MySQL Community Server",
+        ))
+}
+
+container_scenarios!(
+    duplicate_keys_conflict_on_mysql,
+    mysql(),
+    3306,
+    "mysql://root@127.0.0.1:{}",
+    "mysql",
+    [
+        duplicate_user_email_conflicts,
+        rejects_linking_account_of_another_user,
+        duplicate_session_token_conflicts,
+        verification_token_lifecycle,
+    ],
+    [session_token_lookup_uses_index],
+);
@@ -0,0 +1,36 @@
+//! Runs the API scenarios against a real Postgres started with testcontainers.
+//!
+//! Needs Docker. Run with `cargo test --features postgres-tests --test postgres`.
+#![cfg(feature = "postgres-tests")]
+
+#[macro_use]
+mod common;
+
+use testcontainers::images::postgres::Postgres;
+
+container_scenarios!(
+    api_scenarios_on_postgres,
+    Postgres::default(),
+    5432,
+    "postgres://postgres@127.0.0.1:{}",
+    "postgres",
+    [
+        health_is_public,
+        rejects_requests_without_api_key,
+        user_lifecycle,
+        searches_users_by_substring,
+        rejects_invalid_email,
+        account_lifecycle,
+        rejects_linking_account_of_another_user,
+        accepts_numeric_ids,
+        session_lifecycle,
+        revokes_other_sessions,
+        fetches_user_with_accounts_and_sessions,
+        duplicate_user_email_conflicts,
+        duplicate_session_token_conflicts,
+        accepts_expires_in_several_formats,
+        expired_session_is_deleted_on_read,
+        verification_token_lifecycle,
+    ],
+    [session_token_lookup_uses_index],
+);